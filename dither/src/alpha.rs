@@ -0,0 +1,128 @@
+//! Transparency, the palette colors are opaque so the alpha channel gets made
+//! binary, either with a threshold or with an ordered pattern
+use super::ordered::ThresholdMatrix;
+use std::str::FromStr;
+
+/// How the alpha channel is made binary
+#[derive(Clone, Debug, PartialEq)]
+pub enum Coverage {
+    /// The pixels with an alpha of at least the given value are opaque
+    Threshold(u8),
+    /// The alpha is compared with the thresholds of the matrix, which turns
+    /// the partial coverage into a pattern
+    Dithered(ThresholdMatrix),
+}
+
+impl FromStr for Coverage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("dithered") {
+            Ok(Self::Dithered(ThresholdMatrix::bayer(8)))
+        } else {
+            u8::from_str(s).map(Self::Threshold).map_err(|_| {
+                format!(
+                    "Unknown alpha `{}`, expected `dithered` or a threshold from 0 to 255",
+                    s
+                )
+            })
+        }
+    }
+}
+
+/// What to do with the alpha channel of RGBA pixels
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alpha {
+    pub coverage: Coverage,
+    /// The colors are already multiplied by the alpha, like the ones blended by
+    /// OpenGL and the ones expected by the compositor of a transparent window
+    pub premultiplied: bool,
+}
+
+impl Alpha {
+    /// Makes the alpha of `pixels` (RGBA, row by row) binary, the colors are
+    /// brought back to straight alpha so that they can be dithered
+    pub fn prepare(&self, pixels: &mut [u8], width: usize) {
+        let offsets = match self.coverage {
+            Coverage::Threshold(_) => Vec::new(),
+            Coverage::Dithered(ref matrix) => {
+                // One more than the ranks, as an alpha of 0 is always transparent
+                // and one of 255 is always opaque
+                let len = matrix.ranks().len() as u32;
+                matrix
+                    .ranks()
+                    .iter()
+                    .map(|&r| ((r * 2 + 1) * 255 / (len * 2)) as u8 + 1)
+                    .collect()
+            }
+        };
+        for (i, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+            let alpha = pixel[3];
+            if self.premultiplied && alpha > 0 {
+                for c in &mut pixel[..3] {
+                    *c = (*c as u32 * 255 / alpha as u32).min(255) as u8;
+                }
+            }
+            let threshold = match self.coverage {
+                Coverage::Threshold(threshold) => threshold,
+                Coverage::Dithered(ref matrix) => {
+                    let (w, h) = matrix.size();
+                    let (x, y) = (i % width, i / width);
+                    offsets[(y % h) * w + x % w]
+                }
+            };
+            pixel[3] = if alpha >= threshold && alpha > 0 {
+                255
+            } else {
+                0
+            };
+        }
+    }
+
+    /// Multiplies the dithered colors by the binary alpha again if needed,
+    /// which only clears the transparent pixels
+    pub fn finish(&self, pixels: &mut [u8]) {
+        if self.premultiplied {
+            for pixel in pixels.chunks_exact_mut(4).filter(|p| p[3] == 0) {
+                pixel[..3].copy_from_slice(&[0; 3]);
+            }
+        }
+    }
+}
+
+#[test]
+fn binary_alpha() {
+    let ramp: Vec<u8> = (0..=255u8).flat_map(|a| [a / 2, a, 0, a]).collect();
+
+    let mut pixels = ramp.clone();
+    let alpha = Alpha {
+        coverage: Coverage::Threshold(128),
+        premultiplied: true,
+    };
+    alpha.prepare(&mut pixels, 16);
+    for (a, pixel) in pixels.chunks_exact(4).enumerate() {
+        assert_eq!(pixel[3], if a >= 128 { 255 } else { 0 });
+        if a >= 64 {
+            // Back to straight alpha
+            assert!(pixel[0] >= 125 && pixel[0] <= 128, "{:?}", pixel);
+            assert_eq!(pixel[1], 255);
+        }
+    }
+    alpha.finish(&mut pixels);
+    assert!(pixels[..128 * 4].iter().all(|&c| c == 0));
+
+    // The fraction of opaque pixels follows the alpha
+    let alpha = Alpha {
+        coverage: Coverage::Dithered(ThresholdMatrix::bayer(8)),
+        premultiplied: false,
+    };
+    for &a in [0u8, 1, 64, 128, 192, 254, 255].iter() {
+        let mut pixels: Vec<u8> = (0..64).flat_map(|_| [10, 20, 30, a]).collect();
+        alpha.prepare(&mut pixels, 8);
+        alpha.finish(&mut pixels);
+        let opaque = pixels.chunks_exact(4).filter(|p| p[3] == 255).count();
+        let expected = (a as f32 * 64.0 / 255.0).round() as usize;
+        assert!(opaque.abs_diff(expected) <= 1, "{} {}", a, opaque);
+        assert!(pixels.chunks_exact(4).all(|p| p[..3] == [10, 20, 30]));
+    }
+}
@@ -0,0 +1,192 @@
+//! Blue noise threshold matrices generated with the void-and-cluster algorithm
+//! (R. Ulichney, 1993)
+use super::ordered::ThresholdMatrix;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+/// Standard deviation of the gaussian filter used to find voids and clusters
+const SIGMA: f32 = 1.5;
+
+/// Fraction of the pixels that are set in the initial binary pattern
+const INITIAL_DENSITY: f32 = 0.1;
+
+/// A square binary pattern that wraps around its edges, along with the energy of
+/// each cell, that is the sum of the gaussian filter centered on every set cell
+struct Pattern {
+    size: usize,
+    set: Vec<bool>,
+    energy: Vec<f32>,
+    // The filter value for each toroidal offset
+    filter: Vec<f32>,
+}
+
+impl Pattern {
+    fn new(size: usize) -> Self {
+        let mut filter = vec![0.0; size * size];
+        for y in 0..size {
+            for x in 0..size {
+                // Distance on the torus
+                let dx = x.min(size - x) as f32;
+                let dy = y.min(size - y) as f32;
+                filter[y * size + x] = (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+        Self {
+            size,
+            set: vec![false; size * size],
+            energy: vec![0.0; size * size],
+            filter,
+        }
+    }
+
+    fn toggle(&mut self, index: usize) {
+        let sign = if self.set[index] { -1.0 } else { 1.0 };
+        self.set[index] = !self.set[index];
+        let (px, py) = (index % self.size, index / self.size);
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let dx = (x + self.size - px) % self.size;
+                let dy = (y + self.size - py) % self.size;
+                self.energy[y * self.size + x] += sign * self.filter[dy * self.size + dx];
+            }
+        }
+    }
+
+    /// The set cell with the highest energy
+    fn tightest_cluster(&self) -> usize {
+        self.find(true, |a, b| a > b)
+    }
+
+    /// The unset cell with the lowest energy
+    fn largest_void(&self) -> usize {
+        self.find(false, |a, b| a < b)
+    }
+
+    fn find(&self, set: bool, better: impl Fn(f32, f32) -> bool) -> usize {
+        let mut best = None;
+        for (i, &e) in self.energy.iter().enumerate() {
+            if self.set[i] == set && best.is_none_or(|(_, b)| better(e, b)) {
+                best = Some((i, e));
+            }
+        }
+        best.unwrap().0
+    }
+}
+
+/// Simple xorshift generator, the pattern only needs to be random-looking and
+/// it's better if it's always the same
+pub struct XorShift(pub u32);
+
+impl XorShift {
+    pub fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+impl ThresholdMatrix {
+    /// Generates a `size`x`size` blue noise matrix that can be tiled seamlessly
+    ///
+    /// # Panics
+    ///
+    /// If `size` is less than 2
+    pub fn blue_noise(size: usize) -> Self {
+        assert!(size >= 2);
+        let len = size * size;
+        let mut pattern = Pattern::new(size);
+
+        // Initial random pattern
+        let ones = ((len as f32 * INITIAL_DENSITY) as usize).max(1);
+        let mut rng = XorShift(0x9e37_79b9);
+        let mut count = 0;
+        while count < ones {
+            let index = rng.next() as usize % len;
+            if !pattern.set[index] {
+                pattern.toggle(index);
+                count += 1;
+            }
+        }
+        // Move the tightest cluster into the largest void until it would be
+        // put back in the same place, after that the points are evenly spread
+        for _ in 0..len {
+            let cluster = pattern.tightest_cluster();
+            pattern.toggle(cluster);
+            let void = pattern.largest_void();
+            pattern.toggle(void);
+            if void == cluster {
+                break;
+            }
+        }
+
+        let mut ranks = vec![0; len];
+        let prototype = pattern.set.clone();
+        let prototype_energy = pattern.energy.clone();
+        // Phase 1: the ones of the prototype are ranked by removing the tightest cluster
+        for rank in (0..ones).rev() {
+            let cluster = pattern.tightest_cluster();
+            pattern.toggle(cluster);
+            ranks[cluster] = rank as u32;
+        }
+        // Phase 2: starting from the prototype again the largest void gets filled
+        // until every cell has its rank
+        pattern.set = prototype;
+        pattern.energy = prototype_energy;
+        for rank in ones..len {
+            let void = pattern.largest_void();
+            pattern.toggle(void);
+            ranks[void] = rank as u32;
+        }
+        Self::new(size, size, ranks)
+    }
+
+    /// Loads the blue noise matrix from the cache directory, or generates it
+    /// and stores it there if it can't be found
+    pub fn blue_noise_cached(size: usize, dir: &Path) -> io::Result<Self> {
+        let path = dir.join(format!("blue-noise-{}.bin", size));
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let ranks: Vec<u32> = bytes
+                    .chunks_exact(4)
+                    .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect();
+                Self::from_ranks(size, size, ranks).ok_or_else(|| {
+                    let msg = format!("{} is not a valid blue noise matrix", path.display());
+                    io::Error::new(ErrorKind::InvalidData, msg)
+                })
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let matrix = Self::blue_noise(size);
+                let bytes: Vec<u8> = matrix
+                    .ranks()
+                    .iter()
+                    .flat_map(|r| r.to_le_bytes())
+                    .collect();
+                fs::create_dir_all(dir)?;
+                fs::write(&path, bytes)?;
+                Ok(matrix)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn blue_noise_is_spread_out() {
+    let size = 16;
+    let matrix = ThresholdMatrix::blue_noise(size);
+    // The darkest eighth of the thresholds shouldn't have any two cells next to
+    // each other (counting the wrap around), which would be very likely with white noise
+    let dark: Vec<_> = (0..size * size)
+        .filter(|&i| matrix.ranks()[i] < (size * size / 8) as u32)
+        .collect();
+    for &i in &dark {
+        let (x, y) = (i % size, i / size);
+        let right = y * size + (x + 1) % size;
+        let below = (y + 1) % size * size + x;
+        assert!(!dark.contains(&right) && !dark.contains(&below));
+    }
+}
@@ -0,0 +1,392 @@
+//! How the threads of a `WorkerPool` are created: how many of them, their name,
+//! stack size, priority and the cores they run on
+use super::worker::WorkerPool;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+
+/// Default prefix of the names of the threads
+const NAME: &str = "dither-worker";
+
+/// How much CPU time the threads of the pool should get compared to the others
+///
+/// It's only a hint, on Linux it's the nice value of the threads, which can only
+/// be raised if the process has the permission to do so, elsewhere it's ignored
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub const ALL: [Self; 3] = [Self::Low, Self::Normal, Self::High];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Normal => "normal",
+            Self::High => "high",
+        }
+    }
+
+    /// The nice value of the threads on Linux, `None` leaves the inherited one
+    #[cfg(target_os = "linux")]
+    fn nice(self) -> Option<i32> {
+        match self {
+            Self::Low => Some(10),
+            Self::Normal => None,
+            Self::High => Some(-5),
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.name())
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|p| p.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(Self::name).collect();
+                format!(
+                    "Unknown priority `{}`, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Configuration of a `WorkerPool`, by default it has a thread for each CPU the
+/// process can use, named `dither-worker-<index>`
+#[derive(Clone, Debug)]
+pub struct WorkerPoolBuilder {
+    threads: Option<usize>,
+    reserved: usize,
+    smt: bool,
+    name: String,
+    stack_size: Option<usize>,
+    pin: bool,
+    priority: Priority,
+}
+
+impl WorkerPoolBuilder {
+    pub fn new() -> Self {
+        Self {
+            threads: None,
+            reserved: 0,
+            smt: true,
+            name: NAME.to_string(),
+            stack_size: None,
+            pin: false,
+            priority: Priority::Normal,
+        }
+    }
+
+    /// Exact number of threads, instead of deriving it from the CPUs
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Number of CPUs left to the other threads of the program when the number
+    /// of threads is derived from them, like the one that executes the workers,
+    /// there's always at least a thread anyway
+    pub fn reserve(mut self, cpus: usize) -> Self {
+        self.reserved = cpus;
+        self
+    }
+
+    /// Whether the SMT siblings (hyper-threads) of a core count as different
+    /// CPUs, when they don't there is at most a thread per physical core and
+    /// the pinned threads go to different cores
+    ///
+    /// The siblings share the execution units of the core, thus the diffusion
+    /// workers, which wait for each other, don't get much from them, the
+    /// cores are only known on Linux, elsewhere it has no effect
+    pub fn smt(mut self, smt: bool) -> Self {
+        self.smt = smt;
+        self
+    }
+
+    /// Prefix of the names of the threads, which get their index appended
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Size of the stack of each thread in bytes, the default one is the one
+    /// of `std::thread`
+    pub fn stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// Pins each thread to a different CPU among the ones the process can use,
+    /// going around them if there are more threads, it's only supported on Linux
+    pub fn pin(mut self, pin: bool) -> Self {
+        self.pin = pin;
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// The number of threads the pool gets: the exact one if it has been given,
+    /// otherwise the CPUs (or the cores without `smt`) minus the reserved ones
+    ///
+    /// The CPUs are the ones of `std::thread::available_parallelism`, which on
+    /// Linux already takes into account the affinity mask of the process and
+    /// the CPU quota of its cgroup
+    pub fn thread_count(&self) -> usize {
+        if let Some(threads) = self.threads {
+            return threads;
+        }
+        let mut cpus = thread::available_parallelism().map_or(1, |n| n.get());
+        // Without the topology (outside of Linux) the cores can't be told apart
+        // from the other hardware threads, so they are all used
+        let cores = sys::cpus(false).len();
+        if !self.smt && cores > 0 {
+            cpus = cpus.min(cores);
+        }
+        cpus.saturating_sub(self.reserved).max(1)
+    }
+
+    /// Creates the pool with its threads
+    ///
+    /// It fails if the number of threads is zero, if a thread can't be spawned
+    /// or if it can't be pinned to its CPU
+    pub fn build(self) -> io::Result<WorkerPool> {
+        if self.thread_count() == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A worker pool needs at least a thread",
+            ));
+        }
+        if self.pin && !cfg!(target_os = "linux") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Pinning the threads is only supported on Linux",
+            ));
+        }
+        WorkerPool::with_builder(self)
+    }
+
+    /// Spawns the thread of the pool with the given index, it gets pinned and
+    /// its priority gets set before running `work`
+    pub(crate) fn spawn<F>(&self, index: usize, work: F) -> io::Result<thread::JoinHandle<()>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut builder = thread::Builder::new().name(format!("{}-{}", self.name, index));
+        if let Some(bytes) = self.stack_size {
+            builder = builder.stack_size(bytes);
+        }
+        let cpu = if self.pin {
+            let cpus = sys::cpus(self.smt);
+            if cpus.is_empty() {
+                return Err(io::Error::other("Can't find the CPUs of the process"));
+            }
+            Some(cpus[index % cpus.len()])
+        } else {
+            None
+        };
+        let priority = self.priority;
+        // The thread reports whether it could be pinned before starting to work
+        let (sender, receiver) = mpsc::channel();
+        let handle = builder.spawn(move || {
+            let pinned = cpu.map_or(Ok(()), sys::pin);
+            let failed = pinned.is_err();
+            sender.send(pinned).ok();
+            if failed {
+                return;
+            }
+            sys::set_priority(priority);
+            work()
+        })?;
+        match receiver.recv() {
+            Ok(Ok(())) => Ok(handle),
+            Ok(Err(error)) => {
+                let _ = handle.join();
+                Err(error)
+            }
+            Err(_) => Err(io::Error::other("A worker thread died while starting")),
+        }
+    }
+}
+
+impl Default for WorkerPoolBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The system calls for the affinity and the priority of the threads, which
+/// the standard library doesn't expose
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::Priority;
+    use std::fs;
+    use std::io;
+    use std::mem::size_of;
+    use std::os::raw::{c_int, c_uint, c_ulong};
+
+    const BITS: usize = c_ulong::BITS as usize;
+
+    /// The `cpu_set_t` of glibc and musl, a mask of 1024 CPUs
+    #[repr(C)]
+    struct CpuSet {
+        bits: [c_ulong; 1024 / BITS],
+    }
+    const PRIO_PROCESS: c_int = 0;
+
+    extern "C" {
+        fn sched_getaffinity(pid: c_int, size: usize, set: *mut CpuSet) -> c_int;
+        fn sched_setaffinity(pid: c_int, size: usize, set: *const CpuSet) -> c_int;
+        fn setpriority(which: c_int, who: c_uint, priority: c_int) -> c_int;
+    }
+
+    /// The CPUs the process can run on, with `smt` the siblings of each core
+    /// come after the first CPU of every core, otherwise they are left out
+    pub fn cpus(smt: bool) -> Vec<usize> {
+        let mut set = CpuSet {
+            bits: [0; 1024 / BITS],
+        };
+        // SAFETY: the set is as large as the size that is passed
+        if unsafe { sched_getaffinity(0, size_of::<CpuSet>(), &mut set) } != 0 {
+            return Vec::new();
+        }
+        let allowed = (0..1024).filter(|&cpu| set.bits[cpu / BITS] & (1 << (cpu % BITS)) != 0);
+        // The rank of a CPU among the siblings of its core, the topology can
+        // be missing in a container, in which case they are all different cores
+        let mut ranked: Vec<(usize, usize)> = allowed
+            .map(|cpu| {
+                let path = format!(
+                    "/sys/devices/system/cpu/cpu{}/topology/thread_siblings_list",
+                    cpu
+                );
+                let rank = fs::read_to_string(path)
+                    .map(|siblings| siblings_before(&siblings, cpu))
+                    .unwrap_or(0);
+                (rank, cpu)
+            })
+            .filter(|&(rank, _)| smt || rank == 0)
+            .collect();
+        ranked.sort_unstable();
+        ranked.into_iter().map(|(_, cpu)| cpu).collect()
+    }
+
+    /// How many CPUs of a list like `0-1,8-9` come before the given one
+    fn siblings_before(list: &str, cpu: usize) -> usize {
+        list.trim()
+            .split(',')
+            .filter_map(|range| {
+                let (first, last) = range.split_once('-').unwrap_or((range, range));
+                Some((first.parse::<usize>().ok()?, last.parse::<usize>().ok()?))
+            })
+            .map(|(first, last)| (first..=last).filter(|&c| c < cpu).count())
+            .sum()
+    }
+
+    /// Restricts the calling thread to a single CPU
+    pub fn pin(cpu: usize) -> io::Result<()> {
+        if cpu >= 1024 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Can't pin a thread to CPU {}", cpu),
+            ));
+        }
+        let mut set = CpuSet {
+            bits: [0; 1024 / BITS],
+        };
+        set.bits[cpu / BITS] |= 1 << (cpu % BITS);
+        // SAFETY: the set is as large as the size that is passed, and the pid
+        // 0 is the calling thread
+        if unsafe { sched_setaffinity(0, size_of::<CpuSet>(), &set) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Sets the nice value of the calling thread, on Linux each thread has its
+    /// own, the error is ignored as the priority is only a hint
+    pub fn set_priority(priority: Priority) {
+        if let Some(nice) = priority.nice() {
+            // SAFETY: it only changes the priority of the calling thread
+            unsafe { setpriority(PRIO_PROCESS, 0, nice) };
+        }
+    }
+
+    #[test]
+    fn sibling_ranks() {
+        assert_eq!(siblings_before("0,8", 0), 0);
+        assert_eq!(siblings_before("0,8", 8), 1);
+        assert_eq!(siblings_before("2-3", 3), 1);
+        assert_eq!(siblings_before("0-1,8-9\n", 9), 3);
+        assert_eq!(siblings_before("5", 5), 0);
+    }
+}
+
+/// Everything is ignored where it isn't supported, the builder already fails
+/// when it's asked to pin the threads
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use super::Priority;
+    use std::io;
+
+    pub fn cpus(_smt: bool) -> Vec<usize> {
+        Vec::new()
+    }
+
+    pub fn pin(_cpu: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn set_priority(_priority: Priority) {}
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn configured_pool() {
+    let pool = WorkerPoolBuilder::new()
+        .threads(2)
+        .name("configured")
+        .stack_size(256 * 1024)
+        .priority(Priority::Low)
+        .build()
+        .unwrap();
+    assert_eq!(pool.threads(), 2);
+    let name = pool.spawn(|| thread::current().name().map(str::to_string));
+    assert!(name.join().unwrap().unwrap().starts_with("configured-"));
+
+    assert!(WorkerPoolBuilder::new().threads(0).build().is_err());
+    // Even with everything reserved there is a thread
+    assert!(WorkerPoolBuilder::new().reserve(usize::MAX).thread_count() >= 1);
+    assert_eq!("High".parse(), Ok(Priority::High));
+
+    #[cfg(target_os = "linux")]
+    {
+        // A pinned thread can only see its own CPU
+        let pool = WorkerPoolBuilder::new()
+            .threads(2)
+            .pin(true)
+            .build()
+            .unwrap();
+        let cpus = pool.spawn(|| sys::cpus(true));
+        assert_eq!(cpus.join().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,147 @@
+use super::color::{ColorDiff, FixedColor, FloatColor, Palette};
+use super::pixel::PixelFormat;
+use std::ops::AddAssign;
+
+/// What error diffusion keeps for each pixel while the image is dithered, which
+/// is usually the error it received from its neighbours, the color itself stays
+/// in the pixel, stored as `F`
+pub trait Cell {
+    type Error: Copy + Default + AddAssign;
+
+    /// Adds the error that comes from the same row and the one the cell received
+    /// to the color of the pixel, then returns the index of the closest palette
+    /// color along with the quantization error, the pixel is left as it is
+    fn quantize<F: PixelFormat>(
+        &mut self,
+        pixel: &[u8],
+        error: Self::Error,
+        palette: &Palette,
+    ) -> (usize, Self::Error);
+
+    /// Adds the error that comes from the rows above
+    fn diffuse<F: PixelFormat>(&mut self, pixel: &mut [u8], error: Self::Error);
+
+    /// Returns the part of the error that has the given weight
+    fn part(error: Self::Error, weight: i16, divisor: i16) -> Self::Error;
+}
+
+/// Nothing but the pixel itself, the error is added to its color in 8-bit
+/// steps and clamped every time, this is the fastest way but the error gets
+/// truncated and it can only be used with `Metric::Srgb`
+///
+/// The `Gray` pixels only keep the luma of the error they receive
+#[derive(Clone, Copy, Default)]
+pub struct ClampedPixel;
+
+impl Cell for ClampedPixel {
+    type Error = ColorDiff;
+
+    fn quantize<F: PixelFormat>(
+        &mut self,
+        pixel: &[u8],
+        error: ColorDiff,
+        palette: &Palette,
+    ) -> (usize, ColorDiff) {
+        let color = F::read(pixel) + error;
+        let index = palette.closest(color);
+        (index, color - palette.colors()[index])
+    }
+
+    fn diffuse<F: PixelFormat>(&mut self, pixel: &mut [u8], error: ColorDiff) {
+        F::write(pixel, F::read(pixel) + error);
+    }
+
+    fn part(error: ColorDiff, weight: i16, divisor: i16) -> ColorDiff {
+        error * weight / divisor
+    }
+}
+
+/// The error in fixed point, the fractions and the error that goes beyond the
+/// channel range are kept, it's the accurate counterpart of `ClampedPixel` and
+/// it can only be used with `Metric::Srgb` as well
+#[derive(Clone, Copy, Default)]
+pub struct FixedPixel {
+    error: FixedColor,
+}
+
+impl Cell for FixedPixel {
+    type Error = FixedColor;
+
+    fn quantize<F: PixelFormat>(
+        &mut self,
+        pixel: &[u8],
+        error: FixedColor,
+        palette: &Palette,
+    ) -> (usize, FixedColor) {
+        let value = FixedColor::from(F::read(pixel)) + self.error + error;
+        let index = palette.closest_fixed(value);
+        (index, value - FixedColor::from(palette.colors()[index]))
+    }
+
+    fn diffuse<F: PixelFormat>(&mut self, _pixel: &mut [u8], error: FixedColor) {
+        self.error += error;
+    }
+
+    fn part(error: FixedColor, weight: i16, divisor: i16) -> FixedColor {
+        FixedColor(error.0.map(|c| c * weight as i32 / divisor as i32))
+    }
+}
+
+/// The error in the space of the metric of the palette
+#[derive(Clone, Copy, Default)]
+pub struct Pixel {
+    error: FloatColor,
+}
+
+impl Cell for Pixel {
+    type Error = FloatColor;
+
+    fn quantize<F: PixelFormat>(
+        &mut self,
+        pixel: &[u8],
+        error: FloatColor,
+        palette: &Palette,
+    ) -> (usize, FloatColor) {
+        let value = palette.metric().to_space(F::read(pixel)) + self.error + error;
+        let index = palette.closest_point(value);
+        (index, value - palette.points()[index])
+    }
+
+    fn diffuse<F: PixelFormat>(&mut self, _pixel: &mut [u8], error: FloatColor) {
+        self.error += error;
+    }
+
+    fn part(error: FloatColor, weight: i16, divisor: i16) -> FloatColor {
+        error * (weight as f32 / divisor as f32)
+    }
+}
+
+/// The error in linear light, it's added to the decoded color and the result
+/// gets encoded again only to find the closest palette color
+#[derive(Clone, Copy, Default)]
+pub struct LinearPixel {
+    error: FloatColor,
+}
+
+impl Cell for LinearPixel {
+    type Error = FloatColor;
+
+    fn quantize<F: PixelFormat>(
+        &mut self,
+        pixel: &[u8],
+        error: FloatColor,
+        palette: &Palette,
+    ) -> (usize, FloatColor) {
+        let value = FloatColor::linear(F::read(pixel)) + self.error + error;
+        let index = palette.closest_point(palette.metric().linear_to_space(value));
+        (index, value - palette.linear()[index])
+    }
+
+    fn diffuse<F: PixelFormat>(&mut self, _pixel: &mut [u8], error: FloatColor) {
+        self.error += error;
+    }
+
+    fn part(error: FloatColor, weight: i16, divisor: i16) -> FloatColor {
+        error * (weight as f32 / divisor as f32)
+    }
+}
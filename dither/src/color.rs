@@ -0,0 +1,276 @@
+/// A color from its hex code, like `rgb![#ff8000]`, or from its channels
+#[cfg(test)]
+macro_rules! rgb {
+    (#$n:tt) => {
+        Color::from_str(stringify!($n)).unwrap()
+    };
+    ($r:expr, $g:expr, $b: expr) => {
+        Color::new($r, $g, $b)
+    };
+}
+
+mod format;
+mod ops;
+mod preset;
+mod space;
+mod tree;
+pub use format::{PaletteError, PaletteFormat};
+pub use preset::Preset;
+pub use space::{FloatColor, Metric};
+
+use std::fmt;
+use std::mem::{align_of, size_of};
+use std::str::FromStr;
+use tree::KdTree;
+
+/// An 8-bit sRGB color, it has the same layout as 3 bytes so a buffer of
+/// colors can be seen as an RGB image
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+// The casts from the bytes rely on it
+const _: () = assert!(size_of::<Color>() == 3 && align_of::<Color>() == 1);
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    pub fn rgb(self) -> [u8; 3] {
+        [self.r, self.g, self.b]
+    }
+}
+
+impl From<[u8; 3]> for Color {
+    fn from([r, g, b]: [u8; 3]) -> Self {
+        Self { r, g, b }
+    }
+}
+
+fn hex_value(ch: &u8) -> Option<u8> {
+    match ch {
+        ch if ch.is_ascii_digit() => Some(ch - b'0'),
+        ch if ch.is_ascii_alphabetic() => Some((ch.to_ascii_lowercase() - b'a') + 10),
+        _ => None,
+    }
+}
+
+impl FromStr for Color {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = s.as_bytes();
+        if !bytes.iter().all(u8::is_ascii_hexdigit) {
+            Err("The value contains a non hexadecimal digit")
+        } else if bytes.len() != 6 {
+            Err("The value must be made of 6 hex digits (RRGGBB)")
+        } else {
+            let mut hex = bytes.iter().map(hex_value).map(Option::unwrap);
+            Ok(Self {
+                r: (hex.next().unwrap() << 4) + hex.next().unwrap(),
+                g: (hex.next().unwrap() << 4) + hex.next().unwrap(),
+                b: (hex.next().unwrap() << 4) + hex.next().unwrap(),
+            })
+        }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "#{:2x}{:2x}{:2x}", self.r, self.g, self.b)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct ColorDiff {
+    pub r: i16,
+    pub g: i16,
+    pub b: i16,
+}
+
+impl ColorDiff {
+    pub fn length(&self) -> u32 {
+        let rsq = self.r.unsigned_abs() as u32 * self.r.unsigned_abs() as u32;
+        let gsq = self.g.unsigned_abs() as u32 * self.g.unsigned_abs() as u32;
+        let bsq = self.b.unsigned_abs() as u32 * self.b.unsigned_abs() as u32;
+        rsq + gsq + bsq
+    }
+}
+
+/// Color (or color difference) in fixed point, with `FRACTION_BITS` bits below
+/// a single 8-bit step, the values aren't clamped so no error gets lost
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FixedColor(pub [i32; 3]);
+
+impl FixedColor {
+    pub const FRACTION_BITS: u32 = 8;
+
+    /// Squared euclidean length, still in fixed point
+    pub fn length(&self) -> i64 {
+        self.0.iter().map(|&c| c as i64 * c as i64).sum()
+    }
+}
+
+impl From<Color> for FixedColor {
+    fn from(color: Color) -> Self {
+        Self(color.rgb().map(|c| (c as i32) << Self::FRACTION_BITS))
+    }
+}
+
+/// Palettes with more colors than this use a k-d tree to find the closest one
+const LINEAR_SCAN_LIMIT: usize = 16;
+
+pub struct Palette {
+    colors: Vec<Color>,
+    // Only for the big palettes, it works with the `Metric::Srgb` distance
+    tree: Option<KdTree<i32>>,
+    metric: Metric,
+    // The colors converted in the space of the metric
+    points: Vec<FloatColor>,
+    // Tree of the points, only for the big palettes with a euclidean metric
+    points_tree: Option<KdTree<f32>>,
+    // The colors in linear light
+    linear: Vec<FloatColor>,
+    // Whether there's an entry for the transparent pixels after the colors
+    transparent: bool,
+}
+
+impl Palette {
+    pub fn new<T: Into<Vec<Color>>>(colors: T) -> Self {
+        let colors: Vec<_> = colors.into();
+        let tree = if colors.len() > LINEAR_SCAN_LIMIT {
+            Some(KdTree::new(colors.iter().map(|&c| FixedColor::from(c).0)))
+        } else {
+            None
+        };
+        Self {
+            colors,
+            tree,
+            metric: Metric::default(),
+            points: Vec::new(),
+            points_tree: None,
+            linear: Vec::new(),
+            transparent: false,
+        }
+        .with_metric(Metric::default())
+    }
+
+    /// Changes how the distance between the colors is measured
+    pub fn with_metric(mut self, metric: Metric) -> Self {
+        self.points = self.colors.iter().map(|&c| metric.to_space(c)).collect();
+        self.points_tree = if self.colors.len() > LINEAR_SCAN_LIMIT && metric.is_euclidean() {
+            Some(KdTree::new(self.points.iter().map(|p| p.0)))
+        } else {
+            None
+        };
+        self.linear = self
+            .colors
+            .iter()
+            .copied()
+            .map(FloatColor::linear)
+            .collect();
+        self.metric = metric;
+        self
+    }
+
+    pub fn closest(&self, color: Color) -> usize {
+        match self.metric {
+            Metric::Srgb if self.tree.is_some() => self.closest_fixed(color.into()),
+            Metric::Srgb => {
+                self.colors
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &c)| (i, (c - color).length()))
+                    .min_by_key(|&(_, c)| c)
+                    .unwrap()
+                    .0
+            }
+            metric => self.closest_point(metric.to_space(color)),
+        }
+    }
+
+    /// Same as `closest` but with a fixed point color that can be out of range,
+    /// the distance is always the `Metric::Srgb` one
+    pub fn closest_fixed(&self, color: FixedColor) -> usize {
+        if let Some(ref tree) = self.tree {
+            return tree.nearest(color.0);
+        }
+        self.colors
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| (i, (color - FixedColor::from(c)).length()))
+            .min_by_key(|&(_, d)| d)
+            .unwrap()
+            .0
+    }
+
+    /// Same as `closest` but with a color that is already in the space of the metric
+    pub fn closest_point(&self, point: FloatColor) -> usize {
+        if let Some(ref tree) = self.points_tree {
+            return tree.nearest(point.0);
+        }
+        self.points
+            .iter()
+            .map(|&p| self.metric.distance(point, p))
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap()
+            .0
+    }
+
+    /// Adds (or removes) an entry for the transparent pixels, it comes after
+    /// the colors and it's never the closest one, the dithering gives it to the
+    /// pixels that their `PixelFormat` sees as transparent
+    pub fn with_transparent(mut self, transparent: bool) -> Self {
+        self.transparent = transparent;
+        self
+    }
+
+    /// The index of the transparent entry, if any
+    pub fn transparent(&self) -> Option<usize> {
+        Some(self.colors.len()).filter(|_| self.transparent)
+    }
+
+    /// Number of entries, the colors and the transparent one
+    pub fn len(&self) -> usize {
+        self.colors.len() + self.transparent as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    /// The colors converted in the space of the metric
+    pub fn points(&self) -> &[FloatColor] {
+        &self.points
+    }
+
+    /// The colors in linear light
+    pub fn linear(&self) -> &[FloatColor] {
+        &self.linear
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    pub fn find(&self, color: Color) -> Option<usize> {
+        self.colors.iter().position(|&c| c == color)
+    }
+}
+
+#[test]
+fn color_literals() {
+    assert_eq!(rgb!(#0000ff), Color::new(0, 0, 255));
+    assert_eq!(rgb![#FF8001].rgb(), [255, 128, 1]);
+    assert_eq!(rgb!(10, 20, 30), Color::from([10, 20, 30]));
+}
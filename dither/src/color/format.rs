@@ -0,0 +1,416 @@
+//! Palette interchange formats
+use super::{Color, Palette};
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The file formats a palette can be loaded from and saved to, the color names
+/// and the other metadata are not kept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteFormat {
+    /// GIMP palette (`.gpl`)
+    Gimp,
+    /// Paint.NET palette (`.txt`), one `AARRGGBB` color per line
+    PaintNet,
+    /// Adobe Color Table (`.act`), binary with up to 256 colors
+    Adobe,
+    /// JASC palette (`.pal`), used by Paint Shop Pro
+    Jasc,
+    /// One `RRGGBB` color per line (`.hex`), like the ones from Lospec
+    Hex,
+}
+
+impl PaletteFormat {
+    pub const ALL: [Self; 5] = [
+        Self::Gimp,
+        Self::PaintNet,
+        Self::Adobe,
+        Self::Jasc,
+        Self::Hex,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Gimp => "gpl",
+            Self::PaintNet => "txt",
+            Self::Adobe => "act",
+            Self::Jasc => "pal",
+            Self::Hex => "hex",
+        }
+    }
+
+    /// Guesses the format from the extension of the file
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        Self::ALL
+            .iter()
+            .find(|f| f.extension().eq_ignore_ascii_case(extension))
+            .copied()
+    }
+}
+
+impl fmt::Display for PaletteFormat {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.extension())
+    }
+}
+
+impl FromStr for PaletteFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|f| f.extension().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(Self::extension).collect();
+                format!(
+                    "Unknown palette format `{}`, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// A malformed palette, lines and columns start from 1, for the binary formats
+/// the line is always 1 and the column is the byte offset plus one
+#[derive(Debug, PartialEq, Eq)]
+pub struct PaletteError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl PaletteError {
+    fn new(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl Palette {
+    /// Parses a palette in the given format
+    pub fn from_bytes(bytes: &[u8], format: PaletteFormat) -> Result<Self, PaletteError> {
+        let colors = match format {
+            PaletteFormat::Adobe => parse_act(bytes)?,
+            format => {
+                let text = std::str::from_utf8(bytes).map_err(|e| {
+                    let before = &bytes[..e.valid_up_to()];
+                    let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+                    let start = before
+                        .iter()
+                        .rposition(|&b| b == b'\n')
+                        .map_or(0, |i| i + 1);
+                    let column = String::from_utf8_lossy(&before[start..]).chars().count() + 1;
+                    PaletteError::new(line, column, "The file is not valid UTF-8")
+                })?;
+                match format {
+                    PaletteFormat::Gimp => parse_gpl(text)?,
+                    PaletteFormat::PaintNet => parse_lines(text, Some(';'), 8)?,
+                    PaletteFormat::Jasc => parse_jasc(text)?,
+                    _ => parse_lines(text, None, 6)?,
+                }
+            }
+        };
+        Ok(Self::new(colors))
+    }
+
+    /// Serializes the palette in the given format, it fails only if the format
+    /// can't hold all the colors
+    pub fn to_bytes(&self, format: PaletteFormat) -> io::Result<Vec<u8>> {
+        let rgb = self.colors.iter().map(|c| c.rgb());
+        let text = match format {
+            PaletteFormat::Gimp => {
+                let mut text = String::from("GIMP Palette\n#\n");
+                for [r, g, b] in rgb {
+                    text += &format!("{:3} {:3} {:3}\t#{:02x}{:02x}{:02x}\n", r, g, b, r, g, b);
+                }
+                text
+            }
+            PaletteFormat::PaintNet => {
+                let mut text = String::from(";paint.net Palette File\n");
+                for [r, g, b] in rgb {
+                    text += &format!("FF{:02X}{:02X}{:02X}\n", r, g, b);
+                }
+                text
+            }
+            PaletteFormat::Jasc => {
+                let mut text = format!("JASC-PAL\r\n0100\r\n{}\r\n", self.colors.len());
+                for [r, g, b] in rgb {
+                    text += &format!("{} {} {}\r\n", r, g, b);
+                }
+                text
+            }
+            PaletteFormat::Hex => rgb
+                .map(|[r, g, b]| format!("{:02x}{:02x}{:02x}\n", r, g, b))
+                .collect(),
+            PaletteFormat::Adobe => {
+                if self.colors.len() > 256 {
+                    let msg = format!("An ACT palette can't hold {} colors", self.colors.len());
+                    return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+                }
+                let mut bytes: Vec<u8> = rgb.flatten().collect();
+                bytes.resize(768, 0);
+                bytes.extend((self.colors.len() as u16).to_be_bytes());
+                // No transparent color
+                bytes.extend([0xff, 0xff]);
+                return Ok(bytes);
+            }
+        };
+        Ok(text.into_bytes())
+    }
+
+    /// Loads a palette from a file, the format comes from its extension and the
+    /// parsing errors have the `InvalidData` kind with a `PaletteError` inside
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let format = format_of(path)?;
+        let bytes = fs::read(path)?;
+        Self::from_bytes(&bytes, format).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Saves the palette to a file, the format comes from its extension
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let format = format_of(path)?;
+        fs::write(path, self.to_bytes(format)?)
+    }
+}
+
+fn format_of(path: &Path) -> io::Result<PaletteFormat> {
+    PaletteFormat::from_path(path).ok_or_else(|| {
+        let msg = format!("Unknown palette format for {}", path.display());
+        io::Error::new(ErrorKind::InvalidInput, msg)
+    })
+}
+
+/// The fields of the line separated by whitespace, along with their column
+fn fields(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    line.split_whitespace().map(move |field| {
+        let offset = field.as_ptr() as usize - line.as_ptr() as usize;
+        (line[..offset].chars().count() + 1, field)
+    })
+}
+
+/// Reads the red, green and blue decimal values at the start of the line
+fn parse_rgb(line: &str, number: usize) -> Result<Color, PaletteError> {
+    let mut fields = fields(line);
+    let mut rgb = [0; 3];
+    for (value, name) in rgb.iter_mut().zip(["red", "green", "blue"]) {
+        let (column, field) = fields.next().ok_or_else(|| {
+            let column = line.trim_end().chars().count() + 1;
+            PaletteError::new(number, column, format!("Missing the {} value", name))
+        })?;
+        *value = field.parse().map_err(|_| {
+            let msg = format!("Expected a {} value from 0 to 255, found `{}`", name, field);
+            PaletteError::new(number, column, msg)
+        })?;
+    }
+    Ok(rgb.into())
+}
+
+fn parse_gpl(text: &str) -> Result<Vec<Color>, PaletteError> {
+    let mut lines = text.lines().enumerate().map(|(i, l)| (i + 1, l));
+    match lines.next() {
+        Some((_, header)) if header.trim() == "GIMP Palette" => {}
+        _ => {
+            return Err(PaletteError::new(
+                1,
+                1,
+                "Expected the `GIMP Palette` header",
+            ))
+        }
+    }
+    let mut colors = Vec::new();
+    for (number, line) in lines {
+        let trimmed = line.trim();
+        let metadata = ["Name:", "Columns:"].iter().any(|k| trimmed.starts_with(k));
+        if trimmed.is_empty() || trimmed.starts_with('#') || metadata {
+            continue;
+        }
+        colors.push(parse_rgb(line, number)?);
+    }
+    not_empty(colors, text)
+}
+
+fn parse_jasc(text: &str) -> Result<Vec<Color>, PaletteError> {
+    let mut lines = text.lines().enumerate().map(|(i, l)| (i + 1, l));
+    let mut header = |expected: &str| match lines.next() {
+        Some((_, line)) if line.trim() == expected => Ok(()),
+        Some((number, _)) => Err(PaletteError::new(
+            number,
+            1,
+            format!("Expected `{}`", expected),
+        )),
+        None => Err(end_of(text, format!("Expected `{}`", expected))),
+    };
+    header("JASC-PAL")?;
+    header("0100")?;
+    let count: usize = match lines.next() {
+        Some((number, line)) => {
+            let (column, field) = fields(line).next().unwrap_or((1, ""));
+            field.parse().map_err(|_| {
+                let msg = format!("Expected the number of colors, found `{}`", field);
+                PaletteError::new(number, column, msg)
+            })?
+        }
+        None => return Err(end_of(text, "Expected the number of colors")),
+    };
+    let mut colors = Vec::with_capacity(count);
+    for (number, line) in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if colors.len() == count {
+            let msg = format!("Found more than the {} declared colors", count);
+            return Err(PaletteError::new(number, 1, msg));
+        }
+        colors.push(parse_rgb(line, number)?);
+    }
+    if colors.len() < count {
+        let msg = format!("Expected {} colors, found {}", count, colors.len());
+        return Err(end_of(text, msg));
+    }
+    not_empty(colors, text)
+}
+
+/// One hexadecimal color per line, with the given number of digits (where the
+/// first two are the ignored alpha if they are 8), the colors can start with `#`
+/// and the lines that start with `comment` are skipped
+fn parse_lines(
+    text: &str,
+    comment: Option<char>,
+    digits: usize,
+) -> Result<Vec<Color>, PaletteError> {
+    let mut colors = Vec::new();
+    for (number, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l)) {
+        let mut fields = fields(line);
+        let (column, field) = match fields.next() {
+            Some((_, field)) if comment.is_some_and(|c| field.starts_with(c)) => continue,
+            Some(field) => field,
+            None => continue,
+        };
+        let (column, hex) = match field.strip_prefix('#') {
+            Some(hex) => (column + 1, hex),
+            None => (column, field),
+        };
+        if let Some(i) = hex.chars().position(|c| !c.is_ascii_hexdigit()) {
+            let msg = format!(
+                "`{}` is not a hexadecimal digit",
+                hex.chars().nth(i).unwrap()
+            );
+            return Err(PaletteError::new(number, column + i, msg));
+        }
+        if hex.len() != digits {
+            let msg = format!(
+                "Expected {} hexadecimal digits, found {}",
+                digits,
+                hex.len()
+            );
+            return Err(PaletteError::new(number, column, msg));
+        }
+        colors.push(Color::from_str(&hex[digits - 6..]).unwrap());
+        if let Some((column, _)) = fields.next() {
+            return Err(PaletteError::new(number, column, "Expected a single color"));
+        }
+    }
+    not_empty(colors, text)
+}
+
+fn parse_act(bytes: &[u8]) -> Result<Vec<Color>, PaletteError> {
+    let count = match bytes.len() {
+        768 => 256,
+        772 => {
+            let count = u16::from_be_bytes([bytes[768], bytes[769]]) as usize;
+            if count == 0 || count > 256 {
+                let msg = format!("Expected from 1 to 256 colors, found {}", count);
+                return Err(PaletteError::new(1, 769, msg));
+            }
+            count
+        }
+        len => {
+            let msg = format!("Expected 768 or 772 bytes, found {}", len);
+            return Err(PaletteError::new(1, len.min(772) + 1, msg));
+        }
+    };
+    Ok(bytes[..count * 3]
+        .chunks_exact(3)
+        .map(|c| Color::from([c[0], c[1], c[2]]))
+        .collect())
+}
+
+/// An error right after the last character
+fn end_of(text: &str, message: impl Into<String>) -> PaletteError {
+    let line = text.lines().count().max(1);
+    let column = text.lines().last().map_or(0, |l| l.chars().count()) + 1;
+    PaletteError::new(line, column, message)
+}
+
+fn not_empty(colors: Vec<Color>, text: &str) -> Result<Vec<Color>, PaletteError> {
+    if colors.is_empty() {
+        Err(end_of(text, "The palette has no colors"))
+    } else {
+        Ok(colors)
+    }
+}
+
+#[test]
+fn round_trip() {
+    let colors = || -> Vec<Color> {
+        (0..40u8)
+            .map(|i| [i * 6, 255 - i, i.wrapping_mul(97)].into())
+            .collect()
+    };
+    let palette = Palette::new(colors());
+    for &format in PaletteFormat::ALL.iter() {
+        let bytes = palette.to_bytes(format).unwrap();
+        let parsed = Palette::from_bytes(&bytes, format).unwrap();
+        assert!(parsed.colors() == &colors()[..], "{}", format);
+    }
+}
+
+#[test]
+fn error_positions() {
+    let error = |text: &str, format| Palette::from_bytes(text.as_bytes(), format).err().unwrap();
+    let gpl = "GIMP Palette\nName: Test\n#\n  0  0  0 Black\n255 x 255 White\n";
+    assert_eq!(
+        error(gpl, PaletteFormat::Gimp),
+        PaletteError::new(5, 5, "Expected a green value from 0 to 255, found `x`")
+    );
+    let gpl = "GIMP Palette\n255 255\n";
+    assert_eq!(
+        error(gpl, PaletteFormat::Gimp),
+        PaletteError::new(2, 8, "Missing the blue value")
+    );
+    assert_eq!(
+        error("ff0000\n  #00fg00\n", PaletteFormat::Hex),
+        PaletteError::new(2, 7, "`g` is not a hexadecimal digit")
+    );
+    assert_eq!(
+        error(";comment\nFF00FF00 FF000000\n", PaletteFormat::PaintNet),
+        PaletteError::new(2, 10, "Expected a single color")
+    );
+    assert_eq!(
+        error("JASC-PAL\n0100\n3\n0 0 0\n1 1 1\n", PaletteFormat::Jasc),
+        PaletteError::new(5, 6, "Expected 3 colors, found 2")
+    );
+    assert_eq!(
+        Palette::from_bytes(&[0; 100], PaletteFormat::Adobe)
+            .err()
+            .unwrap(),
+        PaletteError::new(1, 101, "Expected 768 or 772 bytes, found 100")
+    );
+}
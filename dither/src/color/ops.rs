@@ -0,0 +1,119 @@
+use super::*;
+use std::ops::*;
+
+impl Add for ColorDiff {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            r: self.r + other.r,
+            g: self.g + other.g,
+            b: self.b + other.b,
+        }
+    }
+}
+
+impl AddAssign for ColorDiff {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for ColorDiff {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            r: self.r - other.r,
+            g: self.g - other.g,
+            b: self.b - other.b,
+        }
+    }
+}
+
+impl Mul<i16> for ColorDiff {
+    type Output = ColorDiff;
+
+    fn mul(self, rhs: i16) -> Self::Output {
+        Self {
+            r: self.r * rhs,
+            g: self.g * rhs,
+            b: self.b * rhs,
+        }
+    }
+}
+
+impl Div<i16> for ColorDiff {
+    type Output = ColorDiff;
+
+    fn div(self, rhs: i16) -> Self::Output {
+        Self {
+            r: self.r / rhs,
+            g: self.g / rhs,
+            b: self.b / rhs,
+        }
+    }
+}
+
+impl Add for FixedColor {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let [a, b, c] = self.0;
+        let [x, y, z] = other.0;
+        Self([a + x, b + y, c + z])
+    }
+}
+
+impl AddAssign for FixedColor {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for FixedColor {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        let [a, b, c] = self.0;
+        let [x, y, z] = other.0;
+        Self([a - x, b - y, c - z])
+    }
+}
+
+impl Sub for Color {
+    type Output = ColorDiff;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::Output {
+            r: self.r as i16 - rhs.r as i16,
+            g: self.g as i16 - rhs.g as i16,
+            b: self.b as i16 - rhs.b as i16,
+        }
+    }
+}
+
+impl Add<ColorDiff> for Color {
+    type Output = Self;
+
+    fn add(self, rhs: ColorDiff) -> Self::Output {
+        let add = |c: u8, d: i16| {
+            if d.is_negative() {
+                c.saturating_sub(d.unsigned_abs() as u8)
+            } else {
+                c.saturating_add(d.unsigned_abs() as u8)
+            }
+        };
+        Self::Output {
+            r: add(self.r, rhs.r),
+            g: add(self.g, rhs.g),
+            b: add(self.b, rhs.b),
+        }
+    }
+}
+
+impl AddAssign<ColorDiff> for Color {
+    fn add_assign(&mut self, rhs: ColorDiff) {
+        *self = *self + rhs;
+    }
+}
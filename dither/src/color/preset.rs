@@ -0,0 +1,286 @@
+//! Built-in palettes
+use super::{Color, Palette};
+use std::fmt;
+use std::str::FromStr;
+
+/// How the colors of a preset are stored
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Entries {
+    /// Listed as `0xRRGGBB`
+    Hex(&'static [u32]),
+    /// Evenly spaced grays, from black to white
+    Ramp(usize),
+    /// Every combination of the given number of evenly spaced levels per channel
+    Cube(usize),
+}
+
+/// A named palette along with where it comes from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Preset {
+    name: &'static str,
+    source: &'static str,
+    entries: Entries,
+}
+
+impl Preset {
+    pub const DEFAULT: Self = Self {
+        name: "default",
+        source: "White, red, green, blue and black",
+        entries: Entries::Hex(&[0xffffff, 0xff0000, 0x00ff00, 0x0000ff, 0x000000]),
+    };
+
+    pub const GAME_BOY: Self = Self {
+        name: "game-boy",
+        source: "Nintendo Game Boy (DMG-01) green shades",
+        entries: Entries::Hex(&[0x0f380f, 0x306230, 0x8bac0f, 0x9bbc0f]),
+    };
+
+    pub const CGA_0: Self = Self {
+        name: "cga-0",
+        source: "IBM CGA 320x200 palette 0, low intensity",
+        entries: Entries::Hex(&[0x000000, 0x00aa00, 0xaa0000, 0xaa5500]),
+    };
+
+    pub const CGA_0_HIGH: Self = Self {
+        name: "cga-0-high",
+        source: "IBM CGA 320x200 palette 0, high intensity",
+        entries: Entries::Hex(&[0x000000, 0x55ff55, 0xff5555, 0xffff55]),
+    };
+
+    pub const CGA_1: Self = Self {
+        name: "cga-1",
+        source: "IBM CGA 320x200 palette 1, low intensity",
+        entries: Entries::Hex(&[0x000000, 0x00aaaa, 0xaa00aa, 0xaaaaaa]),
+    };
+
+    pub const CGA_1_HIGH: Self = Self {
+        name: "cga-1-high",
+        source: "IBM CGA 320x200 palette 1, high intensity",
+        entries: Entries::Hex(&[0x000000, 0x55ffff, 0xff55ff, 0xffffff]),
+    };
+
+    pub const EGA: Self = Self {
+        name: "ega",
+        source: "IBM EGA default 16 colors (the full CGA palette)",
+        entries: Entries::Hex(&[
+            0x000000, 0x0000aa, 0x00aa00, 0x00aaaa, 0xaa0000, 0xaa00aa, 0xaa5500, 0xaaaaaa,
+            0x555555, 0x5555ff, 0x55ff55, 0x55ffff, 0xff5555, 0xff55ff, 0xffff55, 0xffffff,
+        ]),
+    };
+
+    pub const C64: Self = Self {
+        name: "c64",
+        source: "Commodore 64 VIC-II, as measured by Pepto",
+        entries: Entries::Hex(&[
+            0x000000, 0xffffff, 0x68372b, 0x70a4b2, 0x6f3d86, 0x588d43, 0x352879, 0xb8c76f,
+            0x6f4f25, 0x433900, 0x9a6759, 0x444444, 0x6c6c6c, 0x9ad284, 0x6c5eb5, 0x959595,
+        ]),
+    };
+
+    pub const ZX_SPECTRUM: Self = Self {
+        name: "zx-spectrum",
+        source: "Sinclair ZX Spectrum, normal and bright (bright black is black)",
+        entries: Entries::Hex(&[
+            0x000000, 0x0000d7, 0xd70000, 0xd700d7, 0x00d700, 0x00d7d7, 0xd7d700, 0xd7d7d7,
+            0x0000ff, 0xff0000, 0xff00ff, 0x00ff00, 0x00ffff, 0xffff00, 0xffffff,
+        ]),
+    };
+
+    pub const NES: Self = Self {
+        name: "nes",
+        source: "Nintendo Entertainment System 2C02 PPU, without the duplicated blacks",
+        entries: Entries::Hex(&[
+            0x000000, 0xfcfcfc, 0xf8f8f8, 0xbcbcbc, 0x7c7c7c, 0xa4e4fc, 0x3cbcfc, 0x0078f8,
+            0x0000fc, 0xb8b8f8, 0x6888fc, 0x0058f8, 0x0000bc, 0xd8b8f8, 0x9878f8, 0x6844fc,
+            0x4428bc, 0xf8b8f8, 0xf878f8, 0xd800cc, 0x940084, 0xf8a4c0, 0xf85898, 0xe40058,
+            0xa80020, 0xf0d0b0, 0xf87858, 0xf83800, 0xa81000, 0xfce0a8, 0xfca044, 0xe45c10,
+            0x881400, 0xf8d878, 0xf8b800, 0xac7c00, 0x503000, 0xd8f878, 0xb8f818, 0x00b800,
+            0x007800, 0xb8f8b8, 0x58d854, 0x00a800, 0x006800, 0xb8f8d8, 0x58f898, 0x00a844,
+            0x005800, 0x00fcfc, 0x00e8d8, 0x008888, 0x004058, 0xf8d8f8, 0x787878,
+        ]),
+    };
+
+    pub const PICO_8: Self = Self {
+        name: "pico-8",
+        source: "PICO-8 fantasy console by Lexaloffle",
+        entries: Entries::Hex(&[
+            0x000000, 0x1d2b53, 0x7e2553, 0x008751, 0xab5236, 0x5f574f, 0xc2c3c7, 0xfff1e8,
+            0xff004d, 0xffa300, 0xffec27, 0x00e436, 0x29adff, 0x83769c, 0xff77a8, 0xffccaa,
+        ]),
+    };
+
+    pub const MACINTOSH: Self = Self {
+        name: "macintosh",
+        source: "Apple Macintosh 1-bit black and white",
+        entries: Entries::Hex(&[0x000000, 0xffffff]),
+    };
+
+    pub const WEB_SAFE: Self = Self {
+        name: "web-safe",
+        source: "Web-safe colors, 6 levels per channel",
+        entries: Entries::Cube(6),
+    };
+
+    pub const GRAY_2: Self = Self {
+        name: "gray-2",
+        source: "Grayscale ramp, 2 levels",
+        entries: Entries::Ramp(2),
+    };
+
+    pub const GRAY_4: Self = Self {
+        name: "gray-4",
+        source: "Grayscale ramp, 4 levels",
+        entries: Entries::Ramp(4),
+    };
+
+    pub const GRAY_8: Self = Self {
+        name: "gray-8",
+        source: "Grayscale ramp, 8 levels",
+        entries: Entries::Ramp(8),
+    };
+
+    pub const GRAY_16: Self = Self {
+        name: "gray-16",
+        source: "Grayscale ramp, 16 levels",
+        entries: Entries::Ramp(16),
+    };
+
+    pub const ALL: [Self; 17] = [
+        Self::DEFAULT,
+        Self::GAME_BOY,
+        Self::CGA_0,
+        Self::CGA_0_HIGH,
+        Self::CGA_1,
+        Self::CGA_1_HIGH,
+        Self::EGA,
+        Self::C64,
+        Self::ZX_SPECTRUM,
+        Self::NES,
+        Self::PICO_8,
+        Self::MACINTOSH,
+        Self::WEB_SAFE,
+        Self::GRAY_2,
+        Self::GRAY_4,
+        Self::GRAY_8,
+        Self::GRAY_16,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Where the colors come from
+    pub fn source(&self) -> &'static str {
+        self.source
+    }
+
+    /// Number of colors
+    pub fn len(&self) -> usize {
+        match self.entries {
+            Entries::Hex(hex) => hex.len(),
+            Entries::Ramp(levels) => levels,
+            Entries::Cube(levels) => levels * levels * levels,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn colors(&self) -> Vec<Color> {
+        let level = |i: usize, levels: usize| (i * 255 / (levels - 1)) as u8;
+        match self.entries {
+            Entries::Hex(hex) => hex
+                .iter()
+                .map(|&c| Color::from([(c >> 16) as u8, (c >> 8) as u8, c as u8]))
+                .collect(),
+            Entries::Ramp(levels) => (0..levels)
+                .map(|i| Color::from([level(i, levels); 3]))
+                .collect(),
+            Entries::Cube(levels) => (0..self.len())
+                .map(|i| {
+                    let (r, g, b) = (i / levels / levels, i / levels % levels, i % levels);
+                    Color::from([level(r, levels), level(g, levels), level(b, levels)])
+                })
+                .collect(),
+        }
+    }
+
+    pub fn palette(&self) -> Palette {
+        Palette::new(self.colors())
+    }
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.name)
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|p| p.name.eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(Self::name).collect();
+                format!(
+                    "Unknown palette `{}`, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+#[test]
+fn presets() {
+    let counts = [
+        ("default", 5),
+        ("game-boy", 4),
+        ("cga-0", 4),
+        ("cga-1-high", 4),
+        ("ega", 16),
+        ("c64", 16),
+        ("zx-spectrum", 15),
+        ("nes", 55),
+        ("pico-8", 16),
+        ("macintosh", 2),
+        ("web-safe", 216),
+        ("gray-2", 2),
+        ("gray-4", 4),
+        ("gray-8", 8),
+        ("gray-16", 16),
+    ];
+    for &(name, count) in counts.iter() {
+        let preset = Preset::from_str(name).unwrap();
+        assert_eq!(preset.len(), count, "{}", name);
+        assert_eq!(preset.colors().len(), count, "{}", name);
+    }
+    for preset in Preset::ALL.iter() {
+        // No duplicated colors, they would never be chosen
+        let colors = preset.colors();
+        for (i, color) in colors.iter().enumerate() {
+            assert!(!colors[i + 1..].contains(color), "{} {}", preset, color);
+        }
+    }
+
+    let color = |p: Preset, i: usize| p.colors()[i];
+    assert_eq!(color(Preset::GAME_BOY, 3), rgb![#9bbc0f]);
+    assert_eq!(color(Preset::EGA, 6), rgb![#aa5500]);
+    assert_eq!(color(Preset::PICO_8, 8), rgb![#ff004d]);
+    assert_eq!(color(Preset::C64, 9), rgb![#433900]);
+    assert_eq!(color(Preset::WEB_SAFE, 215), rgb![#ffffff]);
+    assert_eq!(color(Preset::WEB_SAFE, 1), rgb![#000033]);
+    assert_eq!(color(Preset::GRAY_4, 1), rgb![#555555]);
+    assert_eq!(color(Preset::GRAY_16, 15), rgb![#ffffff]);
+}
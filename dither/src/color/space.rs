@@ -0,0 +1,323 @@
+use super::*;
+use std::ops::*;
+
+/// Floating point color (or color difference), the meaning of the three
+/// components depends on the `Metric` that produced it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FloatColor(pub [f32; 3]);
+
+/// How the distance between two colors gets measured
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Metric {
+    /// Euclidean distance of the gamma encoded values, uses integer math
+    #[default]
+    Srgb,
+    /// Euclidean distance of the linear light values
+    Linear,
+    /// Euclidean distance of the gamma encoded values weighted by the amount of red
+    Redmean,
+    /// Euclidean distance in CIE Lab (ΔE*76)
+    Cie76,
+    /// CIE ΔE*94 (graphic arts weights)
+    Cie94,
+    /// CIE ΔE*00
+    Ciede2000,
+    /// Euclidean distance in OKLab
+    Oklab,
+}
+
+impl Metric {
+    pub const ALL: [Self; 7] = [
+        Self::Srgb,
+        Self::Linear,
+        Self::Redmean,
+        Self::Cie76,
+        Self::Cie94,
+        Self::Ciede2000,
+        Self::Oklab,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Srgb => "srgb",
+            Self::Linear => "linear",
+            Self::Redmean => "redmean",
+            Self::Cie76 => "cie76",
+            Self::Cie94 => "cie94",
+            Self::Ciede2000 => "ciede2000",
+            Self::Oklab => "oklab",
+        }
+    }
+
+    /// Converts the color in the space where the distance is measured
+    pub fn to_space(self, color: Color) -> FloatColor {
+        match self {
+            Self::Srgb | Self::Redmean => FloatColor(color.rgb().map(f32::from)),
+            metric => metric.linear_to_space(FloatColor::linear(color)),
+        }
+    }
+
+    /// Converts a linear light color in the space where the distance is measured,
+    /// the channels may be out of the `0.0..=1.0` range
+    pub fn linear_to_space(self, color: FloatColor) -> FloatColor {
+        match self {
+            Self::Srgb | Self::Redmean => FloatColor(color.0.map(|c| from_linear(c) * 255.0)),
+            Self::Linear => color,
+            Self::Cie76 | Self::Cie94 | Self::Ciede2000 => lab(color.0),
+            Self::Oklab => oklab(color.0),
+        }
+    }
+
+    /// Whether the distance is the squared euclidean one of the space, which
+    /// lets the big palettes find the closest color with a k-d tree
+    pub fn is_euclidean(&self) -> bool {
+        matches!(self, Self::Srgb | Self::Linear | Self::Cie76 | Self::Oklab)
+    }
+
+    /// Returns a value that grows with the distance between the two colors,
+    /// `reference` is the one used for the weights of the asymmetric metrics
+    pub fn distance(&self, color: FloatColor, reference: FloatColor) -> f32 {
+        let [x, y, z] = (color - reference).0;
+        match self {
+            Self::Srgb | Self::Linear | Self::Cie76 | Self::Oklab => x * x + y * y + z * z,
+            Self::Redmean => {
+                let mean = (color.0[0] + reference.0[0]) / 2.0;
+                (2.0 + mean / 256.0) * x * x + 4.0 * y * y + (2.0 + (255.0 - mean) / 256.0) * z * z
+            }
+            Self::Cie94 => cie94(color, reference),
+            Self::Ciede2000 => ciede2000(color, reference),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.name())
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|m| m.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(Self::name).collect();
+                format!(
+                    "Unknown metric `{}`, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Decodes a gamma encoded sRGB channel to linear light (`0.0..=1.0`)
+pub fn to_linear(channel: u8) -> f32 {
+    let c = channel as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear light channel with the sRGB transfer function, values
+/// out of range are mirrored around zero and extended linearly above one
+fn from_linear(channel: f32) -> f32 {
+    let c = channel.abs();
+    let encoded = if c <= 0.003_130_8 {
+        c * 12.92
+    } else if c <= 1.0 {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    } else {
+        c
+    };
+    encoded.copysign(channel)
+}
+
+/// CIE Lab (D65 white point) from linear sRGB
+fn lab([r, g, b]: [f32; 3]) -> FloatColor {
+    let x = (0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b) / 0.950_47;
+    let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+    let z = (0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b) / 1.088_83;
+    let f = |t: f32| {
+        const DELTA: f32 = 6.0 / 29.0;
+        if t > DELTA * DELTA * DELTA {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    FloatColor([116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)])
+}
+
+/// OKLab from linear sRGB
+fn oklab([r, g, b]: [f32; 3]) -> FloatColor {
+    let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+    FloatColor([
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ])
+}
+
+/// Squared CIE ΔE*94
+fn cie94(FloatColor([l1, a1, b1]): FloatColor, FloatColor([l2, a2, b2]): FloatColor) -> f32 {
+    let c1 = a1.hypot(b1);
+    let c2 = a2.hypot(b2);
+    let dl = l1 - l2;
+    let dc = c1 - c2;
+    let (da, db) = (a1 - a2, b1 - b2);
+    let dh2 = (da * da + db * db - dc * dc).max(0.0);
+    let sc = 1.0 + 0.045 * c2;
+    let sh = 1.0 + 0.015 * c2;
+    dl * dl + (dc / sc) * (dc / sc) + dh2 / (sh * sh)
+}
+
+/// CIE ΔE*00
+fn ciede2000(FloatColor([l1, a1, b1]): FloatColor, FloatColor([l2, a2, b2]): FloatColor) -> f32 {
+    const POW25_7: f32 = 6_103_515_625.0; // 25^7
+    let hue = |b: f32, a: f32| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+
+    let c_bar = (a1.hypot(b1) + a2.hypot(b2)) / 2.0;
+    let g = 0.5 * (1.0 - (c_bar.powi(7) / (c_bar.powi(7) + POW25_7)).sqrt());
+    let (a1, a2) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+    let dl = l2 - l1;
+    let dc = c2 - c1;
+    let dh = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else {
+        h2 - h1 + 360.0
+    };
+    let dh = 2.0 * (c1 * c2).sqrt() * (dh / 2.0).to_radians().sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let cos = |deg: f32| deg.to_radians().cos();
+    let t =
+        1.0 - 0.17 * cos(h_bar - 30.0) + 0.24 * cos(2.0 * h_bar) + 0.32 * cos(3.0 * h_bar + 6.0)
+            - 0.20 * cos(4.0 * h_bar - 63.0);
+    let d_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let rc = 2.0 * (c_bar.powi(7) / (c_bar.powi(7) + POW25_7)).sqrt();
+    let sl = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let sc = 1.0 + 0.045 * c_bar;
+    let sh = 1.0 + 0.015 * c_bar * t;
+    let rt = -(2.0 * d_theta).to_radians().sin() * rc;
+
+    let (l, c, h) = (dl / sl, dc / sc, dh / sh);
+    (l * l + c * c + h * h + rt * c * h).sqrt()
+}
+
+impl FloatColor {
+    /// Decodes the 8-bit sRGB color to linear light
+    pub fn linear(color: Color) -> Self {
+        Self(color.rgb().map(to_linear))
+    }
+}
+
+impl Add for FloatColor {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let [a, b, c] = self.0;
+        let [x, y, z] = other.0;
+        Self([a + x, b + y, c + z])
+    }
+}
+
+impl AddAssign for FloatColor {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for FloatColor {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        let [a, b, c] = self.0;
+        let [x, y, z] = other.0;
+        Self([a - x, b - y, c - z])
+    }
+}
+
+impl Mul<f32> for FloatColor {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self {
+        Self(self.0.map(|v| v * rhs))
+    }
+}
+
+#[test]
+fn color_differences() {
+    // Pair 1 of the CIEDE2000 test data by Sharma, Wu and Dalal
+    let a = FloatColor([50.0, 2.6772, -79.7751]);
+    let b = FloatColor([50.0, 0.0, -82.7485]);
+    assert!((ciede2000(a, b) - 2.0425).abs() < 1e-3);
+
+    let white = lab([1.0; 3]).0;
+    assert!((white[0] - 100.0).abs() < 1e-3 && white[1].abs() < 1e-2 && white[2].abs() < 1e-2);
+    let white = oklab([1.0; 3]).0;
+    assert!((white[0] - 1.0).abs() < 1e-3 && white[1].abs() < 1e-3 && white[2].abs() < 1e-3);
+
+    // A dark gray that is perceptually lighter than its encoded value suggests
+    let gray = rgb![#7a7a7a];
+    let palette = || Palette::new([rgb![#000000], rgb![#ffffff]]);
+    assert_eq!(palette().closest(gray), 0);
+    assert_eq!(palette().with_metric(Metric::Cie76).closest(gray), 1);
+    assert_eq!(palette().with_metric(Metric::Oklab).closest(gray), 1);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn metric_trees() {
+    // The big palettes go through the k-d tree of their points, whatever the metric
+    let grid: Vec<Color> = (0..125u8)
+        .map(|i| Color::new(i % 5 * 60, i / 5 % 5 * 60, i / 25 * 60))
+        .collect();
+    for metric in Metric::ALL {
+        let palette = Palette::new(grid.clone()).with_metric(metric);
+        for i in 0..512u32 {
+            let color = Color::new((i * 37) as u8, (i * 45) as u8, (i * 13) as u8);
+            let point = metric.to_space(color);
+            let expected = (0..grid.len())
+                .map(|j| metric.distance(point, metric.to_space(grid[j])))
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .unwrap()
+                .0;
+            assert_eq!(palette.closest_point(point), expected, "{}", metric);
+        }
+    }
+}
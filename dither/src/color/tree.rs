@@ -0,0 +1,152 @@
+//! K-d tree used to find the closest palette color without comparing all of them
+use std::cmp::Ordering;
+
+/// The type of the coordinates of the points, the distance is the squared
+/// euclidean one, computed the same way as the linear scans of `Palette` so
+/// that the two always agree
+pub trait Coordinate: Copy + PartialOrd {
+    type Distance: Copy + PartialOrd;
+    const FAR: Self::Distance;
+
+    /// Square of the difference along a single axis
+    fn squared(self, other: Self) -> Self::Distance;
+
+    fn length(point: [Self; 3], other: [Self; 3]) -> Self::Distance;
+}
+
+/// Fixed point coordinates, whose distance is exact
+impl Coordinate for i32 {
+    type Distance = i64;
+    const FAR: i64 = i64::MAX;
+
+    fn squared(self, other: Self) -> i64 {
+        let d = (self - other) as i64;
+        d * d
+    }
+
+    fn length(point: [Self; 3], other: [Self; 3]) -> i64 {
+        (0..3).map(|i| point[i].squared(other[i])).sum()
+    }
+}
+
+/// Coordinates in the space of a `Metric`
+impl Coordinate for f32 {
+    type Distance = f32;
+    const FAR: f32 = f32::INFINITY;
+
+    fn squared(self, other: Self) -> f32 {
+        (self - other) * (self - other)
+    }
+
+    fn length(point: [Self; 3], other: [Self; 3]) -> f32 {
+        let [x, y, z] = [0, 1, 2].map(|i| point[i] - other[i]);
+        x * x + y * y + z * z
+    }
+}
+
+/// A palette color along with its index
+#[derive(Clone, Copy)]
+struct Entry<T> {
+    point: [T; 3],
+    index: usize,
+}
+
+/// Balanced k-d tree stored in a flat array, the root of every sub-slice is the
+/// median (along the axis of its depth) and the two halves are its children
+pub struct KdTree<T> {
+    entries: Vec<Entry<T>>,
+}
+
+impl<T: Coordinate> KdTree<T> {
+    pub fn new(points: impl IntoIterator<Item = [T; 3]>) -> Self {
+        let mut entries: Vec<Entry<T>> = points
+            .into_iter()
+            .enumerate()
+            .map(|(index, point)| Entry { point, index })
+            .collect();
+        Self::build(&mut entries, 0);
+        Self { entries }
+    }
+
+    fn build(entries: &mut [Entry<T>], axis: usize) {
+        if entries.len() > 1 {
+            entries.sort_by(|a, b| {
+                a.point[axis]
+                    .partial_cmp(&b.point[axis])
+                    .unwrap_or(Ordering::Equal)
+            });
+            let (left, right) = entries.split_at_mut(entries.len() / 2);
+            Self::build(left, (axis + 1) % 3);
+            Self::build(&mut right[1..], (axis + 1) % 3);
+        }
+    }
+
+    /// Returns the index of the closest point, if more than one is at the
+    /// same distance the one with the lowest index is chosen, like a linear scan would
+    pub fn nearest(&self, target: [T; 3]) -> usize {
+        let mut best = (T::FAR, usize::MAX);
+        Self::search(&self.entries, 0, target, &mut best);
+        best.1
+    }
+
+    fn search(entries: &[Entry<T>], axis: usize, target: [T; 3], best: &mut (T::Distance, usize)) {
+        if entries.is_empty() {
+            return;
+        }
+        let mid = entries.len() / 2;
+        let entry = entries[mid];
+        let distance = T::length(target, entry.point);
+        if (distance, entry.index) < *best {
+            *best = (distance, entry.index);
+        }
+        let (left, right) = (&entries[..mid], &entries[mid + 1..]);
+        let (near, far) = if target[axis] < entry.point[axis] {
+            (left, right)
+        } else {
+            (right, left)
+        };
+        Self::search(near, (axis + 1) % 3, target, best);
+        // Equal distances must be checked as well because of the index
+        if target[axis].squared(entry.point[axis]) <= best.0 {
+            Self::search(far, (axis + 1) % 3, target, best);
+        }
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn matches_linear_scan() {
+    use super::super::blue_noise::XorShift;
+
+    // Palette with duplicates and a lot of equal coordinates, the targets go
+    // out of the channel range like the accumulated error does
+    let mut rng = XorShift(0x1234_5678);
+    let mut random = move |range: i32| (rng.next() % range as u32) as i32;
+    for &len in &[1, 2, 17, 64, 256] {
+        let points: Vec<[i32; 3]> = (0..len)
+            .map(|_| [(); 3].map(|_| (random(8) * 32) << 8))
+            .collect();
+        let floats: Vec<[f32; 3]> = points
+            .iter()
+            .map(|p| p.map(|c| (c >> 8) as f32 / 255.0))
+            .collect();
+        let tree = KdTree::new(points.iter().copied());
+        let float_tree = KdTree::new(floats.iter().copied());
+        for _ in 0..2000 {
+            let target = [(); 3].map(|_| random(512 << 8) - (128 << 8));
+            let expected = (0..len)
+                .min_by_key(|&i| i32::length(target, points[i]))
+                .unwrap();
+            assert_eq!(tree.nearest(target), expected);
+
+            let target = target.map(|c| c as f32 / 65280.0);
+            let expected = (0..len)
+                .map(|i| f32::length(target, floats[i]))
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .unwrap()
+                .0;
+            assert_eq!(float_tree.nearest(target), expected);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Palette indices instead of (or along with) the colors, which is what indexed
+//! images and fixed palette displays need
+
+/// The integer types that can hold a palette index
+pub trait PaletteIndex: Sized {
+    /// Maximum number of colors of the palette
+    const COLORS: usize;
+
+    fn buffer(indices: &mut [Self]) -> IndexBuffer<'_>;
+}
+
+impl PaletteIndex for u8 {
+    const COLORS: usize = 1 << 8;
+
+    fn buffer(indices: &mut [Self]) -> IndexBuffer<'_> {
+        IndexBuffer::U8(indices)
+    }
+}
+
+impl PaletteIndex for u16 {
+    const COLORS: usize = 1 << 16;
+
+    fn buffer(indices: &mut [Self]) -> IndexBuffer<'_> {
+        IndexBuffer::U16(indices)
+    }
+}
+
+/// The palette indices of the pixels, of either type
+pub enum IndexBuffer<'a> {
+    U8(&'a mut [u8]),
+    U16(&'a mut [u16]),
+}
+
+impl<'a> IndexBuffer<'a> {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::U8(indices) => indices.len(),
+            Self::U16(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, i: usize) -> usize {
+        match self {
+            Self::U8(indices) => indices[i] as usize,
+            Self::U16(indices) => indices[i] as usize,
+        }
+    }
+
+    pub fn set(&mut self, i: usize, index: usize) {
+        match self {
+            Self::U8(indices) => indices[i] = index as u8,
+            Self::U16(indices) => indices[i] = index as u16,
+        }
+    }
+
+    pub(crate) fn reborrow(&mut self) -> IndexBuffer<'_> {
+        match self {
+            Self::U8(indices) => IndexBuffer::U8(indices),
+            Self::U16(indices) => IndexBuffer::U16(indices),
+        }
+    }
+
+    /// Splits the buffer in rows of `width` indices
+    pub(crate) fn rows(self, width: usize) -> Vec<Self> {
+        match self {
+            Self::U8(indices) => indices.chunks_mut(width).map(Self::U8).collect(),
+            Self::U16(indices) => indices.chunks_mut(width).map(Self::U16).collect(),
+        }
+    }
+}
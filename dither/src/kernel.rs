@@ -0,0 +1,283 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// An error diffusion kernel, that is the set of weights used to spread the
+/// quantization error of a pixel over its neighbours that have still to be processed
+///
+/// Each weight is stored as `(dx, dy, weight)` where `dx` is the column offset
+/// and `dy` is the row offset (always positive or zero) from the current pixel,
+/// the error that goes to that neighbour is `error * weight / divisor`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiffusionKernel {
+    name: &'static str,
+    weights: &'static [(isize, usize, i16)],
+    divisor: i16,
+}
+
+impl DiffusionKernel {
+    //        | ### | 7/
+    //        | ### | /16
+    //   -----+-----+----
+    //    3/  | 5/  | 1/
+    //    /16 | /16 | /16
+    pub const FLOYD_STEINBERG: Self = Self {
+        name: "floyd-steinberg",
+        weights: &[(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)],
+        divisor: 16,
+    };
+
+    //            X   7   5
+    //    3   5   7   5   3    (1/48)
+    //    1   3   5   3   1
+    pub const JARVIS_JUDICE_NINKE: Self = Self {
+        name: "jarvis-judice-ninke",
+        weights: &[
+            (1, 0, 7),
+            (2, 0, 5),
+            (-2, 1, 3),
+            (-1, 1, 5),
+            (0, 1, 7),
+            (1, 1, 5),
+            (2, 1, 3),
+            (-2, 2, 1),
+            (-1, 2, 3),
+            (0, 2, 5),
+            (1, 2, 3),
+            (2, 2, 1),
+        ],
+        divisor: 48,
+    };
+
+    //            X   8   4
+    //    2   4   8   4   2    (1/42)
+    //    1   2   4   2   1
+    pub const STUCKI: Self = Self {
+        name: "stucki",
+        weights: &[
+            (1, 0, 8),
+            (2, 0, 4),
+            (-2, 1, 2),
+            (-1, 1, 4),
+            (0, 1, 8),
+            (1, 1, 4),
+            (2, 1, 2),
+            (-2, 2, 1),
+            (-1, 2, 2),
+            (0, 2, 4),
+            (1, 2, 2),
+            (2, 2, 1),
+        ],
+        divisor: 42,
+    };
+
+    //            X   8   4
+    //    2   4   8   4   2    (1/32)
+    pub const BURKES: Self = Self {
+        name: "burkes",
+        weights: &[
+            (1, 0, 8),
+            (2, 0, 4),
+            (-2, 1, 2),
+            (-1, 1, 4),
+            (0, 1, 8),
+            (1, 1, 4),
+            (2, 1, 2),
+        ],
+        divisor: 32,
+    };
+
+    //            X   5   3
+    //    2   4   5   4   2    (1/32)
+    //        2   3   2
+    pub const SIERRA: Self = Self {
+        name: "sierra",
+        weights: &[
+            (1, 0, 5),
+            (2, 0, 3),
+            (-2, 1, 2),
+            (-1, 1, 4),
+            (0, 1, 5),
+            (1, 1, 4),
+            (2, 1, 2),
+            (-1, 2, 2),
+            (0, 2, 3),
+            (1, 2, 2),
+        ],
+        divisor: 32,
+    };
+
+    //            X   4   3
+    //    1   2   3   2   1    (1/16)
+    pub const SIERRA_TWO_ROW: Self = Self {
+        name: "sierra-two-row",
+        weights: &[
+            (1, 0, 4),
+            (2, 0, 3),
+            (-2, 1, 1),
+            (-1, 1, 2),
+            (0, 1, 3),
+            (1, 1, 2),
+            (2, 1, 1),
+        ],
+        divisor: 16,
+    };
+
+    //        X   2
+    //    1   1        (1/4)
+    pub const SIERRA_LITE: Self = Self {
+        name: "sierra-lite",
+        weights: &[(1, 0, 2), (-1, 1, 1), (0, 1, 1)],
+        divisor: 4,
+    };
+
+    // Only 6/8 of the error gets propagated, which gives the lighter look
+    //
+    //        X   1   1
+    //    1   1   1        (1/8)
+    //        1
+    pub const ATKINSON: Self = Self {
+        name: "atkinson",
+        weights: &[
+            (1, 0, 1),
+            (2, 0, 1),
+            (-1, 1, 1),
+            (0, 1, 1),
+            (1, 1, 1),
+            (0, 2, 1),
+        ],
+        divisor: 8,
+    };
+
+    //                    X       32
+    //    12      26      30      16         (1/200)
+    //        12      26      12
+    //     5      12      12       5
+    pub const STEVENSON_ARCE: Self = Self {
+        name: "stevenson-arce",
+        weights: &[
+            (2, 0, 32),
+            (-3, 1, 12),
+            (-1, 1, 26),
+            (1, 1, 30),
+            (3, 1, 16),
+            (-2, 2, 12),
+            (0, 2, 26),
+            (2, 2, 12),
+            (-3, 3, 5),
+            (-1, 3, 12),
+            (1, 3, 12),
+            (3, 3, 5),
+        ],
+        divisor: 200,
+    };
+
+    /// All the kernels that are available by name
+    pub const ALL: [Self; 9] = [
+        Self::FLOYD_STEINBERG,
+        Self::JARVIS_JUDICE_NINKE,
+        Self::STUCKI,
+        Self::BURKES,
+        Self::SIERRA,
+        Self::SIERRA_TWO_ROW,
+        Self::SIERRA_LITE,
+        Self::ATKINSON,
+        Self::STEVENSON_ARCE,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the weights as `(dx, dy, weight)` tuples
+    pub fn weights(&self) -> &'static [(isize, usize, i16)] {
+        self.weights
+    }
+
+    pub fn divisor(&self) -> i16 {
+        self.divisor
+    }
+
+    /// Number of rows under the current one that receive some error
+    pub fn rows(&self) -> usize {
+        self.weights.iter().map(|&(_, dy, _)| dy).max().unwrap_or(0)
+    }
+
+    /// Number of pixels on the right of the current one, on the same row,
+    /// that receive some error
+    pub fn ahead(&self) -> usize {
+        self.weights
+            .iter()
+            .filter(|&&(_, dy, _)| dy == 0)
+            .map(|&(dx, _, _)| dx as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns how far on the left and on the right the error reaches on the rows below
+    fn reach(&self) -> (usize, usize) {
+        let below = self.weights.iter().filter(|&&(_, dy, _)| dy > 0);
+        let left = below.clone().map(|&(dx, _, _)| -dx).max().unwrap_or(0);
+        let right = below.map(|&(dx, _, _)| dx).max().unwrap_or(0);
+        (left.max(0) as usize, right.max(0) as usize)
+    }
+
+    /// Number of pixels a worker must stay behind the one of the previous row
+    ///
+    /// When only the next row receives error the worker just has to wait for
+    /// the pixels on its row to receive their last contribution, otherwise two
+    /// workers could write on the same row at once and thus they must also be
+    /// spaced so that the pixels they touch never overlap
+    pub fn lag(&self) -> usize {
+        let (left, right) = self.reach();
+        if self.rows() > 1 {
+            left + right
+        } else {
+            left
+        }
+    }
+}
+
+impl Default for DiffusionKernel {
+    fn default() -> Self {
+        Self::FLOYD_STEINBERG
+    }
+}
+
+impl fmt::Display for DiffusionKernel {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.name)
+    }
+}
+
+impl FromStr for DiffusionKernel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|k| k.name.eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(Self::name).collect();
+                format!(
+                    "Unknown kernel `{}`, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+#[test]
+fn kernel_shapes() {
+    let fs = DiffusionKernel::FLOYD_STEINBERG;
+    assert_eq!((fs.rows(), fs.ahead(), fs.lag()), (1, 1, 1));
+    let sa = DiffusionKernel::STEVENSON_ARCE;
+    assert_eq!((sa.rows(), sa.ahead(), sa.lag()), (3, 2, 6));
+    assert_eq!("Atkinson".parse(), Ok(DiffusionKernel::ATKINSON));
+    // Every kernel but Atkinson propagates the whole error
+    for kernel in DiffusionKernel::ALL.iter().filter(|k| k.name != "atkinson") {
+        let total: i16 = kernel.weights.iter().map(|&(_, _, w)| w).sum();
+        assert_eq!(total, kernel.divisor, "{}", kernel);
+    }
+}
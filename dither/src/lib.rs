@@ -0,0 +1,1103 @@
+//! Real time dithering of images to a fixed (or adaptive) palette
+//!
+//! The image is usually a buffer of bytes seen through an [`Image`], whose
+//! pixels can be in any [`PixelFormat`], and it's dithered in place with
+//! [`dither`], using either error diffusion (with any [`DiffusionKernel`]) or
+//! ordered dithering (with a [`ThresholdMatrix`]). The work is split between
+//! the threads of a [`WorkerPool`], even error diffusion, where each thread
+//! takes every n-th row and follows the progress of the row above.
+//!
+//! ```
+//! use dither::{dither, Image, Method, Preset, Rgba, WorkerPool};
+//!
+//! let (width, height) = (64, 48);
+//! let mut pixels: Vec<u8> = (0..width * height * 4).map(|i| (i % 251) as u8).collect();
+//! let palette = Preset::PICO_8.palette();
+//! let pool = WorkerPool::new(2);
+//!
+//! let mut image = Image::<Rgba>::new(&mut pixels, width, height);
+//! dither(&mut image, &palette, &Method::default(), &pool);
+//! assert!(image.read().iter().all(|&c| palette.find(c).is_some()));
+//! ```
+//!
+//! [`dither_temporal`] keeps the animations from flickering, [`dither_indexed`]
+//! also produces the palette indices (and [`dither_indices`] only them),
+//! [`Quantizer`] generates the palette from the image and [`Alpha`] handles the
+//! transparent pixels.
+mod alpha;
+mod blue_noise;
+mod builder;
+mod cell;
+#[macro_use]
+mod color;
+mod indexed;
+mod kernel;
+mod ordered;
+mod pixel;
+mod quantize;
+mod shared;
+mod task;
+mod temporal;
+mod tiled;
+mod worker;
+use pixel::ImageRows;
+use shared::SharedRows;
+use tiled::diffuse_tiles;
+use worker::DiffusionWorker;
+
+pub use alpha::{Alpha, Coverage};
+pub use builder::{Priority, WorkerPoolBuilder};
+pub use cell::{Cell, ClampedPixel, FixedPixel, LinearPixel, Pixel};
+pub use color::{
+    Color, ColorDiff, FixedColor, FloatColor, Metric, Palette, PaletteError, PaletteFormat, Preset,
+};
+pub use indexed::{IndexBuffer, PaletteIndex};
+pub use kernel::DiffusionKernel;
+pub use ordered::ThresholdMatrix;
+pub use pixel::{Bgr, Bgra, Gray, Image, PixelFormat, Rgb, Rgba};
+pub use quantize::Quantizer;
+pub use task::JoinHandle;
+pub use temporal::{History, Temporal, TemporalPixel};
+pub use tiled::{diffuse_tiled, Difference, Tiling};
+pub use worker::{Scope, WorkerPanic, WorkerPool};
+
+/// The order in which error diffusion processes the pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScanOrder {
+    /// Every row from left to right
+    Raster,
+    /// The odd rows from right to left with the kernel mirrored, this avoids the
+    /// diagonal artefacts but as each row starts where the previous one ends they
+    /// can't be processed at the same time
+    Serpentine,
+}
+
+/// The dithering algorithm
+#[derive(Clone, Debug, PartialEq)]
+pub enum Method {
+    /// Error diffusion, each row depends on the previous ones
+    Diffusion {
+        kernel: DiffusionKernel,
+        order: ScanOrder,
+        /// Decodes the colors to linear light and diffuses the error there,
+        /// otherwise the error is added to the gamma encoded values, which
+        /// makes the gradients darker than they should be
+        gamma_correct: bool,
+        /// Keeps the error in fixed point without clamping it, otherwise it's
+        /// truncated to whole 8-bit steps and what goes beyond the channel range
+        /// is lost, which leaves bands in the flat areas, the other metrics
+        /// and `gamma_correct` always use floats
+        accurate: bool,
+        /// Splits the image in tiles that are dithered independently, which is
+        /// faster on large images but only approximates the result
+        tiles: Option<Tiling>,
+    },
+    /// Ordered dithering (with a Bayer or a blue noise matrix),
+    /// the pixels are independent from each other
+    Ordered(ThresholdMatrix),
+}
+
+impl Default for Method {
+    fn default() -> Self {
+        Self::Diffusion {
+            kernel: DiffusionKernel::default(),
+            order: ScanOrder::Raster,
+            gamma_correct: false,
+            accurate: false,
+            tiles: None,
+        }
+    }
+}
+
+/// Dithers the image in place, the pixels are read and written through their
+/// `PixelFormat`, whatever it is and whatever the stride
+pub fn dither<F: PixelFormat>(
+    image: &mut Image<F>,
+    palette: &Palette,
+    method: &Method,
+    pool: &WorkerPool,
+) {
+    dither_rows::<F>(image.rows_to_dither(), palette, method, pool)
+}
+
+/// Same as `dither` but it also writes the index of the palette color of every
+/// pixel in `indices`, which can be `u8` or `u16`, as soon as the color is chosen,
+/// the transparent pixels get the transparent entry of the palette if it has one
+///
+/// # Panics
+///
+/// If the palette has too many colors for the index type or if there isn't an
+/// index for each pixel
+pub fn dither_indexed<F: PixelFormat, I: PaletteIndex>(
+    image: &mut Image<F>,
+    palette: &Palette,
+    method: &Method,
+    indices: &mut [I],
+    pool: &WorkerPool,
+) {
+    assert!(palette.len() <= I::COLORS);
+    assert!(indices.len() == image.width() * image.height());
+    let rows = image.rows_to_dither().with_indices(I::buffer(indices));
+    dither_rows::<F>(rows, palette, method, pool)
+}
+
+/// Same as `dither_indexed` but the pixels are only read, the indices are all
+/// that gets written, as the error can't go into the pixels it's always kept
+/// in fixed point with `Metric::Srgb` (like `accurate` does)
+///
+/// # Panics
+///
+/// If the palette has too many colors for the index type or if there isn't an
+/// index for each pixel
+pub fn dither_indices<F: PixelFormat, I: PaletteIndex>(
+    image: &Image<F>,
+    palette: &Palette,
+    method: &Method,
+    indices: &mut [I],
+    pool: &WorkerPool,
+) {
+    assert!(palette.len() <= I::COLORS);
+    assert!(indices.len() == image.width() * image.height());
+    let rows = image.rows_to_read().with_indices(I::buffer(indices));
+    dither_rows::<F>(rows, palette, method, pool)
+}
+
+/// Dithers the rows with the cells that fit the method, the error goes into
+/// the pixels only if they are written
+fn dither_rows<F: PixelFormat>(
+    rows: ImageRows,
+    palette: &Palette,
+    method: &Method,
+    pool: &WorkerPool,
+) {
+    match *method {
+        Method::Diffusion {
+            kernel,
+            order,
+            gamma_correct,
+            accurate,
+            tiles,
+        } => {
+            let serpentine = order == ScanOrder::Serpentine;
+            let len = rows.width * rows.height();
+            if gamma_correct {
+                // The colors are decoded once, they get encoded again by the palette metric
+                let mut cells = vec![LinearPixel::default(); len];
+                diffuse_cells::<_, F>(rows, &mut cells, palette, kernel, serpentine, tiles, pool)
+            } else if palette.metric() != Metric::Srgb {
+                // The error is kept in the space of the metric
+                let mut cells = vec![Pixel::default(); len];
+                diffuse_cells::<_, F>(rows, &mut cells, palette, kernel, serpentine, tiles, pool)
+            } else if accurate || rows.read_only() {
+                let mut cells = vec![FixedPixel::default(); len];
+                diffuse_cells::<_, F>(rows, &mut cells, palette, kernel, serpentine, tiles, pool)
+            } else {
+                // The error goes straight into the pixels, the cells take no space
+                let mut cells = vec![ClampedPixel; len];
+                diffuse_cells::<_, F>(rows, &mut cells, palette, kernel, serpentine, tiles, pool)
+            }
+        }
+        Method::Ordered(ref matrix) => {
+            pool.scope(|scope| ordered::dither::<F>(rows, palette, matrix, scope))
+        }
+    }
+}
+
+/// Same as `dither` but error diffusion takes into account the previous frame,
+/// which is stored in `temporal`, the error is always kept in fixed point and the
+/// closest color is the one of `Metric::Srgb`
+pub fn dither_temporal<F: PixelFormat>(
+    image: &mut Image<F>,
+    palette: &Palette,
+    method: &Method,
+    temporal: &mut Temporal,
+    pool: &WorkerPool,
+) {
+    let (width, height) = (image.width(), image.height());
+    match *method {
+        Method::Diffusion {
+            kernel,
+            order,
+            tiles,
+            ..
+        } => {
+            let serpentine = order == ScanOrder::Serpentine;
+            let threshold = temporal.threshold();
+            let history = temporal.history(width, height);
+            let mut cells: Vec<TemporalPixel> = history
+                .iter()
+                .map(|&history| TemporalPixel::new(history, threshold))
+                .collect();
+            let rows = image.rows_to_dither();
+            diffuse_cells::<_, F>(rows, &mut cells, palette, kernel, serpentine, tiles, pool);
+            for (history, cell) in history.iter_mut().zip(&cells) {
+                *history = cell.history();
+            }
+        }
+        // Ordered dithering is already stable
+        Method::Ordered(_) => dither(image, palette, method, pool),
+    }
+}
+
+/// Diffuses the error over the rows, with a cell for each pixel, either in
+/// tiles or all at once
+fn diffuse_cells<C: Cell + Clone + Send, F: PixelFormat>(
+    rows: ImageRows,
+    cells: &mut [C],
+    palette: &Palette,
+    kernel: DiffusionKernel,
+    serpentine: bool,
+    tiles: Option<Tiling>,
+    pool: &WorkerPool,
+) {
+    match tiles {
+        Some(tiling) => {
+            diffuse_tiles::<C, F>(rows, cells, palette, kernel, serpentine, tiling, pool)
+        }
+        None => pool
+            .scope(|scope| diffuse_rows::<C, F>(rows, cells, palette, kernel, serpentine, scope)),
+    }
+}
+
+/// Error diffusion over an image with a cell for each one of its pixels, row
+/// by row, the calling thread and the threads of the pool take the rows one at
+/// a time, and process a pixel as soon as the row above is far enough ahead
+/// that it has received all its error
+///
+/// The calling thread alone can get the image done, so it works even if all
+/// the threads of the pool are busy, or if the scope belongs to a worker of
+/// the same pool
+///
+/// This is what `dither` uses, with the cells of the crate (`ClampedPixel`,
+/// `Pixel`, `FixedPixel`, `LinearPixel` and `TemporalPixel`), but any other
+/// `Cell` works as well, the image is done once the scope returns
+///
+/// # Panics
+///
+/// If there isn't a cell for each pixel
+pub fn diffuse<'scope, C: Cell + Send + 'scope, F: PixelFormat + 'scope>(
+    image: &'scope mut Image<'_, F>,
+    cells: &'scope mut [C],
+    palette: &'scope Palette,
+    kernel: DiffusionKernel,
+    serpentine: bool,
+    scope: &'scope Scope<'scope, '_>,
+) {
+    let rows = image.rows_to_dither();
+    diffuse_rows::<C, F>(rows, cells, palette, kernel, serpentine, scope)
+}
+
+/// Same as `diffuse` for the rows the workers get
+fn diffuse_rows<'scope, C: Cell + Send + 'scope, F: PixelFormat + 'scope>(
+    rows: ImageRows<'scope>,
+    cells: &'scope mut [C],
+    palette: &'scope Palette,
+    kernel: DiffusionKernel,
+    serpentine: bool,
+    scope: &'scope Scope<'scope, '_>,
+) {
+    let height = rows.height();
+    let rows = SharedRows::new(cells, rows);
+    // In serpentine order each row waits for the whole previous one,
+    // so more threads would only spin
+    let helpers = if serpentine {
+        0
+    } else {
+        scope.threads().min(height).saturating_sub(1)
+    };
+    for _ in 0..helpers {
+        scope.execute(DiffusionWorker::<C, F>::new(
+            rows.clone(),
+            palette,
+            kernel,
+            serpentine,
+        ));
+    }
+    scope.run(DiffusionWorker::<C, F>::new(
+        rows, palette, kernel, serpentine,
+    ));
+}
+
+/// Image for the tests, the first two channels go across the whole range along
+/// each axis and the third one changes from a pixel to the next
+#[cfg(test)]
+fn gradient(width: usize, height: usize) -> Vec<[u8; 3]> {
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            [
+                (x * 255 / width) as u8,
+                (y * 255 / height) as u8,
+                ((x ^ y) % 256) as u8,
+            ]
+        })
+        .collect()
+}
+
+/// Error diffusion with the given options and without gamma correction, for the tests
+#[cfg(test)]
+fn diffusion(
+    kernel: DiffusionKernel,
+    order: ScanOrder,
+    accurate: bool,
+    tiles: Option<Tiling>,
+) -> Method {
+    Method::Diffusion {
+        kernel,
+        order,
+        gamma_correct: false,
+        accurate,
+        tiles,
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn matches_serial_dithering() {
+    use color::ColorDiff;
+    use std::str::FromStr;
+
+    let (width, height) = (37, 23);
+    let image = gradient(width, height);
+    let palette = Palette::new([rgb![#ffffff], rgb![#ff0000], rgb![#000000]]);
+    let pool = WorkerPool::new(3);
+
+    let orders = [ScanOrder::Raster, ScanOrder::Serpentine];
+    for (&kernel, &order) in DiffusionKernel::ALL
+        .iter()
+        .flat_map(|k| orders.iter().map(move |o| (k, o)))
+    {
+        // Single threaded reference, the error for the current row is
+        // accumulated and added just once like the workers do
+        let mut pixels: Vec<Color> = image.iter().map(|&c| Color::from(c)).collect();
+        for y in 0..height {
+            let reversed = order == ScanOrder::Serpentine && y % 2 == 1;
+            // Maps the position along the scan direction to the column
+            let column = |s: usize| if reversed { width - 1 - s } else { s };
+            let mut ahead = vec![ColorDiff::default(); kernel.ahead()];
+            for s in 0..width {
+                let old = &mut pixels[y * width + column(s)];
+                if !ahead.is_empty() {
+                    *old += ahead.remove(0);
+                    ahead.push(ColorDiff::default());
+                }
+                let new = palette.colors()[palette.closest(*old)];
+                let error = *old - new;
+                *old = new;
+                for &(dx, dy, w) in kernel.weights() {
+                    let (s, y) = (s as isize + dx, y + dy);
+                    if s >= 0 && s < width as isize && y < height {
+                        let part = error * w / kernel.divisor();
+                        if dy == 0 {
+                            ahead[dx as usize - 1] += part;
+                        } else {
+                            pixels[y * width + column(s as usize)] += part;
+                        }
+                    }
+                }
+            }
+        }
+        let expected: Vec<[u8; 3]> = pixels.iter().map(|c| c.rgb()).collect();
+
+        let mut actual = image.clone();
+        let method = diffusion(kernel, order, false, None);
+        let mut output = Image::<Rgb>::new(actual.as_flattened_mut(), width, height);
+        dither(&mut output, &palette, &method, &pool);
+        assert!(expected == actual, "{} {:?}", kernel, order);
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn float_diffusion_is_deterministic() {
+    use std::str::FromStr;
+
+    let (width, height) = (29, 17);
+    let image = gradient(width, height);
+    let run = |palette: &Palette, gamma_correct: bool, threads: usize| {
+        let method = Method::Diffusion {
+            kernel: DiffusionKernel::STUCKI,
+            order: ScanOrder::Raster,
+            gamma_correct,
+            accurate: false,
+            tiles: None,
+        };
+        let pool = WorkerPool::new(threads);
+        let mut output = image.clone();
+        let mut image = Image::<Rgb>::new(output.as_flattened_mut(), width, height);
+        dither(&mut image, palette, &method, &pool);
+        output
+    };
+    for &metric in Metric::ALL.iter() {
+        let palette = Palette::new([rgb![#ffffff], rgb![#00ff00], rgb![#000000]]);
+        let palette = palette.with_metric(metric);
+        // A single thread processes the rows one after the other
+        for &gamma_correct in &[false, true] {
+            let serial = run(&palette, gamma_correct, 1);
+            assert!(serial == run(&palette, gamma_correct, 4), "{}", metric);
+        }
+    }
+}
+
+#[test]
+fn gamma_correct_gradient() {
+    use std::str::FromStr;
+
+    // #bcbcbc emits half of the light of white, so about half of the pixels
+    // must be white, while diffusing the encoded value makes it almost three quarters
+    let (width, height) = (32, 32);
+    let gray = 0xbc;
+    let palette = Palette::new([rgb![#000000], rgb![#ffffff]]);
+    let pool = WorkerPool::new(2);
+    let method = Method::Diffusion {
+        kernel: DiffusionKernel::FLOYD_STEINBERG,
+        order: ScanOrder::Raster,
+        gamma_correct: true,
+        accurate: false,
+        tiles: None,
+    };
+    let mut output = vec![[gray; 3]; width * height];
+    let mut image = Image::<Rgb>::new(output.as_flattened_mut(), width, height);
+    dither(&mut image, &palette, &method, &pool);
+    let white = output.iter().filter(|c| c[0] == 255).count() as f32;
+    assert!((white / (width * height) as f32 - 0.5).abs() < 0.02);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn accurate_matches_serial_dithering() {
+    use color::FixedColor;
+    use std::str::FromStr;
+
+    let (width, height) = (31, 19);
+    let image = gradient(width, height);
+    let palette = Palette::new([rgb![#ffffff], rgb![#0000ff], rgb![#000000]]);
+    let pool = WorkerPool::new(3);
+
+    let orders = [ScanOrder::Raster, ScanOrder::Serpentine];
+    for (&kernel, &order) in DiffusionKernel::ALL
+        .iter()
+        .flat_map(|k| orders.iter().map(move |o| (k, o)))
+    {
+        // Single threaded reference with one error buffer for the whole image,
+        // the order of the integer additions doesn't change the result
+        let mut expected = image.clone();
+        let mut errors = vec![[0i32; 3]; width * height];
+        for y in 0..height {
+            let reversed = order == ScanOrder::Serpentine && y % 2 == 1;
+            let column = |s: usize| if reversed { width - 1 - s } else { s };
+            for s in 0..width {
+                let i = y * width + column(s);
+                let value = FixedColor::from(Color::from(expected[i])) + FixedColor(errors[i]);
+                let index = palette.closest_fixed(value);
+                let new = palette.colors()[index];
+                expected[i] = new.rgb();
+                let error = value - FixedColor::from(new);
+                for &(dx, dy, w) in kernel.weights() {
+                    let (s, y) = (s as isize + dx, y + dy);
+                    if s >= 0 && s < width as isize && y < height {
+                        let j = y * width + column(s as usize);
+                        for (e, c) in errors[j].iter_mut().zip(error.0) {
+                            *e += c * w as i32 / kernel.divisor() as i32;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut actual = image.clone();
+        let method = diffusion(kernel, order, true, None);
+        let mut output = Image::<Rgb>::new(actual.as_flattened_mut(), width, height);
+        dither(&mut output, &palette, &method, &pool);
+        assert!(expected == actual, "{} {:?}", kernel, order);
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn accurate_flat_areas() {
+    use std::str::FromStr;
+
+    // With a dark flat gray the truncated error never reaches the next pixels,
+    // so the area stays black instead of having about 1 white pixel in 128
+    let (width, height) = (256, 256);
+    let palette = Palette::new([rgb![#000000], rgb![#ffffff]]);
+    let pool = WorkerPool::new(2);
+    let whites = |accurate: bool| {
+        let method = diffusion(
+            DiffusionKernel::FLOYD_STEINBERG,
+            ScanOrder::Raster,
+            accurate,
+            None,
+        );
+        let mut output = vec![[2u8; 3]; width * height];
+        let mut image = Image::<Rgb>::new(output.as_flattened_mut(), width, height);
+        dither(&mut image, &palette, &method, &pool);
+        output.iter().filter(|c| c[0] == 255).count()
+    };
+    assert_eq!(whites(false), 0);
+    let expected = width * height * 2 / 255;
+    // Some of the error leaves the image from the edges
+    assert!(whites(true) > expected * 3 / 4);
+}
+
+/// Per frame dithering cost with different palette sizes, it can be run with
+/// `cargo test --release -- --ignored --nocapture palette_lookup_benchmark`
+#[test]
+#[ignore]
+fn palette_lookup_benchmark() {
+    use std::time::Instant;
+
+    let (width, height) = (800, 600);
+    let frames = 10;
+    let image = gradient(width, height);
+    let pool = WorkerPool::new(num_cpus::get());
+    for &len in &[16, 64, 256] {
+        // Evenly spread over the cube, plus some grays to reach the size
+        let side = (len as f32).cbrt() as usize;
+        let mut colors: Vec<Color> = (0..side * side * side)
+            .map(|i| {
+                let level = |v: usize| (v * 255 / (side - 1)) as u8;
+                [
+                    level(i % side),
+                    level(i / side % side),
+                    level(i / side / side),
+                ]
+                .into()
+            })
+            .collect();
+        let missing = len - colors.len();
+        colors.extend((1..=missing).map(|i| {
+            let v = (i * 255 / (missing + 1)) as u8;
+            Color::from([v; 3])
+        }));
+        let palette = Palette::new(colors);
+
+        let start = Instant::now();
+        let mut found = 0;
+        for color in image.iter().map(|&c| Color::from(c)) {
+            found += palette.closest(color);
+        }
+        let lookup = start.elapsed();
+        let start = Instant::now();
+        for color in image.iter().map(|&c| Color::from(c)) {
+            found -= (0..len)
+                .min_by_key(|&i| (palette.colors()[i] - color).length())
+                .unwrap();
+        }
+        let scan = start.elapsed();
+        assert_eq!(found, 0);
+
+        let method = Method::default();
+        let start = Instant::now();
+        for _ in 0..frames {
+            let mut frame = image.clone();
+            let mut image = Image::<Rgb>::new(frame.as_flattened_mut(), width, height);
+            dither(&mut image, &palette, &method, &pool);
+        }
+        println!(
+            "{:3} colors: {:?} per frame ({:?} to look up every pixel, {:?} with a linear scan)",
+            len,
+            start.elapsed() / frames,
+            lookup,
+            scan
+        );
+    }
+}
+
+/// Error diffusion time with 1 to 64 threads, it can be run with
+/// `cargo test --release -- --ignored --nocapture wavefront_scaling_benchmark`
+#[test]
+#[ignore]
+fn wavefront_scaling_benchmark() {
+    use std::time::Instant;
+
+    let (width, height) = (1280, 720);
+    let frames = 10;
+    let image = gradient(width, height);
+    let palette = Preset::PICO_8.palette();
+    let method = Method::default();
+    let mut reference = None;
+    let mut single = None;
+    for &threads in &[1, 2, 4, 8, 16, 32, 64] {
+        let pool = WorkerPool::new(threads);
+        let mut output = image.clone();
+        let start = Instant::now();
+        for _ in 0..frames {
+            output.copy_from_slice(&image);
+            let mut image = Image::<Rgb>::new(output.as_flattened_mut(), width, height);
+            dither(&mut image, &palette, &method, &pool);
+        }
+        let elapsed = start.elapsed() / frames;
+        let single = *single.get_or_insert(elapsed);
+        println!(
+            "{:2} threads: {:?} per frame, {:.2}x",
+            threads,
+            elapsed,
+            single.as_secs_f64() / elapsed.as_secs_f64()
+        );
+        // The result never depends on the number of threads
+        assert_eq!(reference.get_or_insert_with(|| output.clone()), &output);
+    }
+}
+
+/// Time of a 4K frame with tiles of different sizes and how much the result
+/// differs from the exact one, it can be run with
+/// `cargo test --release -- --ignored --nocapture tiled_diffusion_benchmark`
+#[test]
+#[ignore]
+fn tiled_diffusion_benchmark() {
+    use std::time::Instant;
+
+    let (width, height) = (3840, 2160);
+    let image = gradient(width, height);
+    let palette = Preset::PICO_8.palette();
+    let pool = WorkerPool::new(num_cpus::get());
+    let mut exact = None;
+    for &tiles in &[None, Some("512:64"), Some("128:32"), Some("32:8")] {
+        let method = diffusion(
+            DiffusionKernel::FLOYD_STEINBERG,
+            ScanOrder::Raster,
+            false,
+            tiles.map(|t| t.parse().unwrap()),
+        );
+        let mut output = image.clone();
+        let start = Instant::now();
+        let mut image = Image::<Rgb>::new(output.as_flattened_mut(), width, height);
+        dither(&mut image, &palette, &method, &pool);
+        let elapsed = start.elapsed();
+        let colors = image.read();
+        let exact = exact.get_or_insert_with(|| colors.clone());
+        println!(
+            "{:>6}: {:?}, {}",
+            tiles.unwrap_or("exact"),
+            elapsed,
+            Difference::between(exact, &colors, width)
+        );
+    }
+}
+
+/// Wall and CPU time of a frame while the rows wait for each other, it can be run with
+/// `cargo test --release -- --ignored --nocapture waiting_cpu_benchmark`
+#[test]
+#[ignore]
+#[cfg(target_os = "linux")]
+fn waiting_cpu_benchmark() {
+    use std::time::Instant;
+
+    /// CPU time used by all the threads of the process (user and system), the
+    /// clock ticks of `/proc` are assumed to be of 10ms
+    fn cpu_time() -> std::time::Duration {
+        let stat = std::fs::read_to_string("/proc/self/stat").unwrap();
+        // The fields after the name, which is between parentheses, start from the third one
+        let fields: Vec<&str> = stat
+            .rsplit(')')
+            .next()
+            .unwrap()
+            .split_whitespace()
+            .collect();
+        let ticks: u64 = fields[11].parse::<u64>().unwrap() + fields[12].parse::<u64>().unwrap();
+        std::time::Duration::from_millis(ticks * 10)
+    }
+
+    let (width, height) = (1280, 720);
+    let frames = 20;
+    let image = gradient(width, height);
+    let palette = Preset::PICO_8.palette();
+    let orders = [ScanOrder::Raster, ScanOrder::Serpentine];
+    for (&threads, &order) in [1, 2, 4, 8]
+        .iter()
+        .flat_map(|t| orders.iter().map(move |o| (t, o)))
+    {
+        let method = diffusion(DiffusionKernel::FLOYD_STEINBERG, order, false, None);
+        let pool = WorkerPool::new(threads);
+        let mut output = image.clone();
+        let (start, cpu) = (Instant::now(), cpu_time());
+        for _ in 0..frames {
+            output.copy_from_slice(&image);
+            let mut image = Image::<Rgb>::new(output.as_flattened_mut(), width, height);
+            dither(&mut image, &palette, &method, &pool);
+        }
+        let (wall, cpu) = (start.elapsed(), cpu_time() - cpu);
+        // Above 1 the threads keep more than one core busy, which is only
+        // useful work as long as they don't spin
+        println!(
+            "{} threads, {:?}: {:?} per frame, {:?} of CPU, CPU/wall {:.2}",
+            threads,
+            order,
+            wall / frames,
+            cpu / frames,
+            cpu.as_secs_f64() / wall.as_secs_f64()
+        );
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn temporal_stability() {
+    use std::str::FromStr;
+
+    let (width, height) = (48, 32);
+    let frame = |shift: u8| {
+        let mut image = gradient(width, height);
+        // The second frame is slightly brighter on the right half
+        for (i, pixel) in image.iter_mut().enumerate() {
+            if i % width > width / 2 {
+                pixel[0] = pixel[0].saturating_add(shift);
+            }
+        }
+        image
+    };
+    let palette = Palette::new([
+        rgb![#ffffff],
+        rgb![#ff0000],
+        rgb![#00ff00],
+        rgb![#0000ff],
+        rgb![#000000],
+    ]);
+    let method = Method::default();
+    let pool = WorkerPool::new(3);
+    let run = |image: Vec<[u8; 3]>, temporal: Option<&mut Temporal>| {
+        let mut output = image;
+        let mut image = Image::<Rgb>::new(output.as_flattened_mut(), width, height);
+        match temporal {
+            Some(t) => dither_temporal(&mut image, &palette, &method, t, &pool),
+            None => dither(&mut image, &palette, &method, &pool),
+        }
+        output
+    };
+    let flips = |a: &[[u8; 3]], b: &[[u8; 3]]| a.iter().zip(b).filter(|(a, b)| a != b).count();
+
+    let independent = flips(&run(frame(0), None), &run(frame(2), None));
+    let mut temporal = Temporal::new(4);
+    let first = run(frame(0), Some(&mut temporal));
+    // The same frame again is dithered exactly the same way
+    assert_eq!(flips(&first, &run(frame(0), Some(&mut temporal))), 0);
+    let second = run(frame(2), Some(&mut temporal));
+    assert!(flips(&first, &second) * 4 < independent);
+
+    // A different size starts over
+    let mut fresh = Temporal::new(4);
+    let (width, height) = (height, width);
+    let image: Vec<[u8; 3]> = frame(0);
+    let mut a = image.clone();
+    let mut image_a = Image::<Rgb>::new(a.as_flattened_mut(), width, height);
+    dither_temporal(&mut image_a, &palette, &method, &mut temporal, &pool);
+    let mut b = image;
+    let mut image_b = Image::<Rgb>::new(b.as_flattened_mut(), width, height);
+    dither_temporal(&mut image_b, &palette, &method, &mut fresh, &pool);
+    assert!(a == b);
+
+    // A threshold past the largest change is clamped instead of overflowing
+    let mut clamped = Temporal::new(u32::MAX);
+    assert_eq!(clamped.threshold(), Temporal::MAX_THRESHOLD);
+    let mut largest = Temporal::new(Temporal::MAX_THRESHOLD);
+    run(frame(0), Some(&mut clamped));
+    run(frame(0), Some(&mut largest));
+    assert_eq!(
+        run(frame(64), Some(&mut clamped)),
+        run(frame(64), Some(&mut largest))
+    );
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn indexed_output() {
+    use std::str::FromStr;
+
+    let (width, height) = (33, 21);
+    let image = gradient(width, height);
+    let palette = Palette::new([
+        rgb![#ffffff],
+        rgb![#ff0000],
+        rgb![#00ff00],
+        rgb![#0000ff],
+        rgb![#000000],
+    ]);
+    let pool = WorkerPool::new(3);
+    let accurate = diffusion(DiffusionKernel::default(), ScanOrder::Raster, true, None);
+    let tiled = diffusion(
+        DiffusionKernel::default(),
+        ScanOrder::Raster,
+        true,
+        Some(Tiling::new(8)),
+    );
+    let methods = [
+        Method::default(),
+        accurate,
+        tiled,
+        Method::Ordered(ThresholdMatrix::bayer(4)),
+    ];
+    for method in methods.iter() {
+        let mut colors = image.clone();
+        let mut small = vec![0u8; width * height];
+        let mut rgb = Image::<Rgb>::new(colors.as_flattened_mut(), width, height);
+        dither_indexed(&mut rgb, &palette, method, &mut small, &pool);
+
+        // The pixels of another layout end up the same
+        let mut bgra: Vec<u8> = image.iter().flat_map(|&[r, g, b]| [b, g, r, 0]).collect();
+        let mut wide = vec![0u16; width * height];
+        let mut other = Image::<Bgra>::new(&mut bgra, width, height);
+        dither_indexed(&mut other, &palette, method, &mut wide, &pool);
+        let expected: Vec<[u8; 3]> = other.read().iter().map(|c| c.rgb()).collect();
+
+        assert!(colors == expected);
+        for ((&color, &i), &j) in colors.iter().zip(&small).zip(&wide) {
+            assert_eq!(palette.colors()[i as usize], Color::from(color));
+            assert_eq!(i as u16, j);
+        }
+
+        // Only the indices, the pixels are left as they are
+        let mut source = image.clone();
+        let mut indices = vec![0u8; width * height];
+        let only = Image::<Rgb>::new(source.as_flattened_mut(), width, height);
+        dither_indices(&only, &palette, method, &mut indices, &pool);
+        assert!(source == image);
+        // Without pixels to clamp the error in it's always kept in fixed point
+        if *method != Method::default() {
+            assert!(indices == small);
+        }
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn transparent_edges() {
+    // A gradient with a transparent band on its right, the opaque pixels next
+    // to it come out the same as when the band isn't there
+    let (width, height, opaque) = (40, 24, 25);
+    let colors = gradient(width, height);
+    let rgba = |width_out: usize| -> Vec<u8> {
+        colors
+            .chunks_exact(width)
+            .flat_map(|row| row[..width_out].iter().enumerate())
+            .flat_map(|(x, &[r, g, b])| [r, g, b, if x < opaque { 255 } else { 0 }])
+            .collect()
+    };
+    let palette = Preset::PICO_8.palette().with_transparent(true);
+    let transparent = palette.transparent().unwrap();
+    let pool = WorkerPool::new(3);
+
+    let mut methods = vec![Method::Ordered(ThresholdMatrix::bayer(4))];
+    for &kernel in DiffusionKernel::ALL.iter() {
+        for order in [ScanOrder::Raster, ScanOrder::Serpentine] {
+            for accurate in [false, true] {
+                methods.push(diffusion(kernel, order, accurate, None));
+            }
+        }
+    }
+    for method in methods.iter() {
+        let mut full = rgba(width);
+        let mut full_indices = vec![0u8; width * height];
+        let mut image = Image::<Rgba>::new(&mut full, width, height);
+        dither_indexed(&mut image, &palette, method, &mut full_indices, &pool);
+
+        let mut cropped = rgba(opaque);
+        let mut indices = vec![0u8; opaque * height];
+        let mut image = Image::<Rgba>::new(&mut cropped, opaque, height);
+        dither_indexed(&mut image, &palette, method, &mut indices, &pool);
+
+        let source = rgba(width);
+        for y in 0..height {
+            let row = &full[y * width * 4..][..width * 4];
+            let (left, right) = row.split_at(opaque * 4);
+            assert_eq!(
+                left,
+                &cropped[y * opaque * 4..][..opaque * 4],
+                "{:?}",
+                method
+            );
+            assert_eq!(right, &source[y * width * 4..][opaque * 4..width * 4]);
+
+            let row = &full_indices[y * width..][..width];
+            assert_eq!(
+                row[..opaque],
+                indices[y * opaque..][..opaque],
+                "{:?}",
+                method
+            );
+            assert!(row[opaque..].iter().all(|&i| i as usize == transparent));
+        }
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn tiled_diffusion() {
+    let (width, height) = (96, 128);
+    let image = gradient(width, height);
+    let palette = Preset::PICO_8.palette();
+    let pool = WorkerPool::new(3);
+    let run = |tiles| {
+        let method = diffusion(
+            DiffusionKernel::FLOYD_STEINBERG,
+            ScanOrder::Raster,
+            false,
+            tiles,
+        );
+        let mut output = image.clone();
+        let mut image = Image::<Rgb>::new(output.as_flattened_mut(), width, height);
+        dither(&mut image, &palette, &method, &pool);
+        image.read()
+    };
+    let exact = run(None);
+    // A single tile is the whole image
+    let single = run(Some(Tiling::new(height)));
+    assert_eq!(Difference::between(&exact, &single, width).mismatched, 0.0);
+    let blurred: Vec<_> = [(16, 0), (16, 16), (32, 8)]
+        .iter()
+        .map(|&(rows, seam)| {
+            let tiled = run(Some(Tiling { rows, seam }));
+            assert!(tiled.iter().all(|&c| palette.find(c).is_some()));
+            let difference = Difference::between(&exact, &tiled, width);
+            assert!(difference.blurred < 2.0, "{}:{} {}", rows, seam, difference);
+            difference.blurred
+        })
+        .collect();
+    // The seam gets the error to the level it has in the rest of the image
+    assert!(blurred[1] < blurred[0]);
+    assert_eq!("32".parse(), Ok(Tiling { rows: 32, seam: 8 }));
+    assert_eq!("32:4".parse(), Ok(Tiling { rows: 32, seam: 4 }));
+    assert!("4:8".parse::<Tiling>().is_err());
+}
+
+#[test]
+fn worker_panics() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    let (width, height) = (40, 30);
+    let mut pixels = vec![[100u8; 3]; width * height];
+    let pool = WorkerPool::new(3);
+
+    // The empty palette makes every diffusion worker panic, the rows below
+    // must not wait for them and the panic gets to the caller
+    let empty = Palette::new(Vec::<Color>::new());
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut image = Image::<Rgb>::new(pixels.as_flattened_mut(), width, height);
+        dither(&mut image, &empty, &Method::default(), &pool);
+    }));
+    let panic = result.unwrap_err();
+    assert!(panic.downcast_ref::<WorkerPanic>().is_some());
+
+    // The panic of a task goes to its handle, or to the scope if it was dropped
+    let result = pool.try_scope(|scope| {
+        let handle = scope.spawn(|| panic!("joined"));
+        assert!(handle.join().unwrap_err().to_string().contains("joined"));
+        drop(scope.spawn(|| panic!("dropped")));
+    });
+    assert!(result.unwrap_err().to_string().contains("dropped"));
+
+    // The pool still works
+    let palette = Preset::PICO_8.palette();
+    let mut image = Image::<Rgb>::new(pixels.as_flattened_mut(), width, height);
+    dither(&mut image, &palette, &Method::default(), &pool);
+    assert!(image.read().iter().all(|&c| palette.find(c).is_some()));
+}
+
+#[test]
+fn nested_dithering() {
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    // Small enough for Miri, which checks the workers run on the calling thread
+    let (width, height) = (8, 6);
+    let image = gradient(width, height);
+    let palette = Arc::new(Preset::PICO_8.palette());
+    let run = move |mut pixels: Vec<[u8; 3]>, pool: &WorkerPool| {
+        let mut output = Image::<Rgb>::new(pixels.as_flattened_mut(), width, height);
+        dither(&mut output, &palette, &Method::default(), pool);
+        pixels
+    };
+    let expected = run(image.clone(), &WorkerPool::new(1));
+    // Keeps a thread of the pool busy until the sender gets dropped
+    let block = |pool: &WorkerPool| {
+        let (sender, receiver) = mpsc::channel::<()>();
+        let handle = pool.spawn(move || receiver.recv().unwrap_err());
+        (sender, handle)
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let pool = Arc::new(WorkerPool::new(2));
+        let mut results = Vec::new();
+
+        // All the threads are busy, the caller does the whole image
+        let blocked = [block(&pool), block(&pool)];
+        results.push(run(image.clone(), &pool));
+        for (unblock, handle) in blocked {
+            drop(unblock);
+            handle.join().unwrap();
+        }
+
+        // A task dithers with its own pool while the other thread is busy
+        let (unblock, handle) = block(&pool);
+        let nested = {
+            let (pool, run, image) = (Arc::clone(&pool), run.clone(), image.clone());
+            pool.clone().spawn(move || run(image, &pool))
+        };
+        results.push(nested.join().unwrap());
+        drop(unblock);
+        handle.join().unwrap();
+
+        // More tasks of a scope than threads, all dithering at once
+        pool.scope(|scope| {
+            let handles: Vec<_> = (0..3)
+                .map(|_| scope.spawn(|| run(image.clone(), &pool)))
+                .collect();
+            results.extend(handles.into_iter().map(|h| h.join().unwrap()));
+        });
+        sender.send(results).unwrap();
+    });
+    let results = receiver
+        .recv_timeout(Duration::from_secs(60))
+        .expect("The nested dithering is stuck");
+    assert_eq!(results.len(), 5);
+    assert!(results.iter().all(|result| *result == expected));
+}
+
+#[test]
+fn custom_cells() {
+    // A cell of another crate, which only keeps the error of the level of gray
+    #[derive(Clone, Copy, Default)]
+    struct Level(i32);
+
+    impl Cell for Level {
+        type Error = i32;
+
+        fn quantize<F: PixelFormat>(
+            &mut self,
+            pixel: &[u8],
+            error: i32,
+            palette: &Palette,
+        ) -> (usize, i32) {
+            let value = F::read(pixel).g as i32 + self.0 + error;
+            let gray = value.clamp(0, 255) as u8;
+            let index = palette.closest(Color::new(gray, gray, gray));
+            (index, value - palette.colors()[index].g as i32)
+        }
+
+        fn diffuse<F: PixelFormat>(&mut self, _pixel: &mut [u8], error: i32) {
+            self.0 += error;
+        }
+
+        fn part(error: i32, weight: i16, divisor: i16) -> i32 {
+            error * weight as i32 / divisor as i32
+        }
+    }
+
+    let (width, height) = (16, 8);
+    let mut pixels: Vec<u8> = (0..width * height)
+        .map(|i| (i % width * 16) as u8)
+        .collect();
+    let mut image = Image::<Gray>::new(&mut pixels, width, height);
+    let mut cells = vec![Level::default(); width * height];
+    let palette = Palette::new([Color::new(0, 0, 0), Color::new(255, 255, 255)]);
+    let pool = WorkerPool::new(2);
+    let kernel = DiffusionKernel::FLOYD_STEINBERG;
+    pool.scope(|scope| diffuse(&mut image, &mut cells, &palette, kernel, false, scope));
+    // The error diffusion keeps the average level of the image
+    let white: usize = pixels.iter().filter(|&&p| p == 255).count();
+    assert!(pixels.iter().all(|&p| p == 0 || p == 255));
+    assert!(white.abs_diff(width * height * 120 / 255) <= width);
+}
@@ -0,0 +1,213 @@
+use super::color::{ColorDiff, Palette};
+use super::pixel::{ImageRows, PixelFormat};
+use super::worker::Scope;
+use std::marker::PhantomData;
+
+/// A matrix of thresholds that gets tiled over the image, each cell holds the
+/// rank of the threshold (from `0` to `width * height - 1`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThresholdMatrix {
+    width: usize,
+    height: usize,
+    ranks: Vec<u32>,
+}
+
+impl ThresholdMatrix {
+    /// Constructs a matrix from its ranks, given row by row
+    ///
+    /// # Panics
+    ///
+    /// If the number of ranks is not `width * height` or if they are not a
+    /// permutation of `0..width * height`
+    pub fn new(width: usize, height: usize, ranks: Vec<u32>) -> Self {
+        Self::from_ranks(width, height, ranks).expect("Invalid threshold matrix")
+    }
+
+    /// Same as `new` but returns `None` if the ranks are not valid
+    pub fn from_ranks(width: usize, height: usize, ranks: Vec<u32>) -> Option<Self> {
+        let mut sorted = ranks.clone();
+        sorted.sort_unstable();
+        let permutation = sorted.iter().enumerate().all(|(i, &r)| r as usize == i);
+        if width > 0 && height > 0 && ranks.len() == width * height && permutation {
+            Some(Self {
+                width,
+                height,
+                ranks,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Constructs the `size`x`size` Bayer matrix, when `size` is not a power of
+    /// two it's the top left corner of the next one, ranked again
+    ///
+    /// # Panics
+    ///
+    /// If `size` is zero
+    pub fn bayer(size: usize) -> Self {
+        assert!(size > 0);
+        let full = size.next_power_of_two();
+        // Each step doubles the size of the matrix by placing four copies of it:
+        //     | 4M + 0 | 4M + 2 |
+        //     | 4M + 3 | 4M + 1 |
+        let mut ranks = vec![0];
+        let mut n = 1;
+        while n < full {
+            let mut next = vec![0; 4 * n * n];
+            for y in 0..n {
+                for x in 0..n {
+                    let m = 4 * ranks[y * n + x];
+                    next[y * 2 * n + x] = m;
+                    next[y * 2 * n + x + n] = m + 2;
+                    next[(y + n) * 2 * n + x] = m + 3;
+                    next[(y + n) * 2 * n + x + n] = m + 1;
+                }
+            }
+            ranks = next;
+            n *= 2;
+        }
+        // The order of the cells of the corner is kept, but the gaps left by the
+        // ones that are cut out are closed
+        let mut corner: Vec<usize> = (0..size * size).collect();
+        corner.sort_unstable_by_key(|&i| ranks[i / size * full + i % size]);
+        let mut cropped = vec![0; size * size];
+        for (rank, &i) in corner.iter().enumerate() {
+            cropped[i] = rank as u32;
+        }
+        Self::new(size, size, cropped)
+    }
+
+    /// Width and height
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn ranks(&self) -> &[u32] {
+        &self.ranks
+    }
+
+    /// Computes the value added to each channel of the pixels that fall on each cell,
+    /// they are evenly distributed in `-spread / 2..spread / 2`
+    fn offsets(&self, spread: f32) -> Vec<i16> {
+        let len = self.ranks.len() as f32;
+        self.ranks
+            .iter()
+            .map(|&r| ((r as f32 + 0.5) / len - 0.5) * spread)
+            .map(|v| v.round() as i16)
+            .collect()
+    }
+}
+
+/// Returns the spread of the thresholds for the given palette, which is
+/// roughly the distance between its colors if they were evenly distributed
+pub fn spread(palette: &Palette) -> f32 {
+    256.0 / (palette.colors().len() as f32).cbrt()
+}
+
+/// Ordered dithering worker, it processes a band of contiguous rows of pixels
+/// stored as `F`
+pub struct OrderedWorker<'a, F> {
+    palette: &'a Palette,
+    matrix: &'a ThresholdMatrix,
+    offsets: Vec<i16>,
+    rows: ImageRows<'a>,
+    first_row: usize,
+    format: PhantomData<fn() -> F>,
+}
+
+impl<'a, F: PixelFormat> OrderedWorker<'a, F> {
+    pub(crate) fn new(
+        rows: ImageRows<'a>,
+        first_row: usize,
+        palette: &'a Palette,
+        matrix: &'a ThresholdMatrix,
+    ) -> Self {
+        Self {
+            palette,
+            matrix,
+            offsets: matrix.offsets(spread(palette)),
+            rows,
+            first_row,
+            format: PhantomData,
+        }
+    }
+
+    pub fn run(&mut self) {
+        let (w, h) = (self.matrix.width, self.matrix.height);
+        let mut indices = self.rows.indices.iter_mut();
+        for (y, row) in (self.first_row..).zip(self.rows.pixels.iter_mut()) {
+            let mut indices = indices.next();
+            for x in 0..self.rows.width {
+                let pixel = x * F::SIZE..(x + 1) * F::SIZE;
+                let color = F::read(&row.get()[pixel.clone()]);
+                let transparent = F::transparent(&row.get()[pixel.clone()]);
+                let index = match self.palette.transparent().filter(|_| transparent) {
+                    // The pixel is left as it is
+                    Some(index) => index,
+                    None => {
+                        let offset = self.offsets[(y % h) * w + x % w];
+                        let offset = ColorDiff {
+                            r: offset,
+                            g: offset,
+                            b: offset,
+                        };
+                        let index = self.palette.closest(color + offset);
+                        if let Some(row) = row.get_mut() {
+                            F::write(&mut row[pixel], self.palette.colors()[index]);
+                        }
+                        index
+                    }
+                };
+                if let Some(ref mut indices) = indices {
+                    indices.set(x, index);
+                }
+            }
+        }
+    }
+}
+
+/// Splits the image in bands, one for each thread of the pool
+pub(crate) fn dither<'scope, F: PixelFormat + 'scope>(
+    rows: ImageRows<'scope>,
+    palette: &'scope Palette,
+    matrix: &'scope ThresholdMatrix,
+    scope: &'scope Scope<'scope, '_>,
+) {
+    let (width, height) = (rows.width, rows.height());
+    let threads = scope.threads().max(1);
+    let band = height.div_ceil(threads).max(1);
+    let mut pixels = rows.pixels.into_iter();
+    let mut indices = rows.indices.into_iter();
+    for first_row in (0..height).step_by(band) {
+        let rows = ImageRows {
+            width,
+            pixels: pixels.by_ref().take(band).collect(),
+            indices: indices.by_ref().take(band).collect(),
+        };
+        scope.execute(OrderedWorker::<F>::new(rows, first_row, palette, matrix));
+    }
+}
+
+#[test]
+fn bayer_matrices() {
+    assert_eq!(ThresholdMatrix::bayer(2).ranks, [0, 2, 3, 1]);
+    #[rustfmt::skip]
+    assert_eq!(ThresholdMatrix::bayer(4).ranks, [
+         0,  8,  2, 10,
+        12,  4, 14,  6,
+         3, 11,  1,  9,
+        15,  7, 13,  5,
+    ]);
+    // The constructor checks that the ranks are a permutation
+    ThresholdMatrix::bayer(32);
+    #[rustfmt::skip]
+    assert_eq!(ThresholdMatrix::bayer(3).ranks, [
+        0, 5, 2,
+        7, 4, 8,
+        3, 6, 1,
+    ]);
+    for size in [5, 6, 7, 12] {
+        ThresholdMatrix::bayer(size);
+    }
+}
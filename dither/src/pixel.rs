@@ -0,0 +1,303 @@
+//! The layouts of the pixel buffers that can be dithered
+use super::color::Color;
+use super::indexed::IndexBuffer;
+use std::marker::PhantomData;
+
+/// How a pixel is stored in a buffer of bytes
+pub trait PixelFormat {
+    /// Number of bytes of a pixel
+    const SIZE: usize;
+
+    fn read(pixel: &[u8]) -> Color;
+
+    /// Stores the color, the other channels (like alpha) are left as they are
+    fn write(pixel: &mut [u8], color: Color);
+
+    /// Whether the pixel is fully transparent, when the palette has an entry
+    /// for these pixels they get it and they are otherwise left out, they take
+    /// none of the error of their neighbours and they give none
+    fn transparent(_pixel: &[u8]) -> bool {
+        false
+    }
+}
+
+/// Red, green and blue, one byte each
+pub struct Rgb;
+
+/// Red, green, blue and alpha
+pub struct Rgba;
+
+/// Blue, green and red
+pub struct Bgr;
+
+/// Blue, green, red and alpha
+pub struct Bgra;
+
+/// A single luma byte, the palette colors are written with their luma as well
+pub struct Gray;
+
+impl PixelFormat for Rgb {
+    const SIZE: usize = 3;
+
+    fn read(pixel: &[u8]) -> Color {
+        Color::new(pixel[0], pixel[1], pixel[2])
+    }
+
+    fn write(pixel: &mut [u8], color: Color) {
+        pixel[..3].copy_from_slice(&color.rgb());
+    }
+}
+
+impl PixelFormat for Rgba {
+    const SIZE: usize = 4;
+
+    fn read(pixel: &[u8]) -> Color {
+        Rgb::read(pixel)
+    }
+
+    fn write(pixel: &mut [u8], color: Color) {
+        Rgb::write(pixel, color)
+    }
+
+    fn transparent(pixel: &[u8]) -> bool {
+        pixel[3] == 0
+    }
+}
+
+impl PixelFormat for Bgr {
+    const SIZE: usize = 3;
+
+    fn read(pixel: &[u8]) -> Color {
+        Color::new(pixel[2], pixel[1], pixel[0])
+    }
+
+    fn write(pixel: &mut [u8], color: Color) {
+        pixel[..3].copy_from_slice(&[color.b, color.g, color.r]);
+    }
+}
+
+impl PixelFormat for Bgra {
+    const SIZE: usize = 4;
+
+    fn read(pixel: &[u8]) -> Color {
+        Bgr::read(pixel)
+    }
+
+    fn write(pixel: &mut [u8], color: Color) {
+        Bgr::write(pixel, color)
+    }
+
+    fn transparent(pixel: &[u8]) -> bool {
+        pixel[3] == 0
+    }
+}
+
+impl PixelFormat for Gray {
+    const SIZE: usize = 1;
+
+    fn read(pixel: &[u8]) -> Color {
+        Color::new(pixel[0], pixel[0], pixel[0])
+    }
+
+    fn write(pixel: &mut [u8], color: Color) {
+        // Rec. 601 luma
+        let luma = color.r as u32 * 299 + color.g as u32 * 587 + color.b as u32 * 114;
+        pixel[0] = ((luma + 500) / 1000) as u8;
+    }
+}
+
+/// A buffer of pixels stored row by row, each row starts `stride` bytes after
+/// the previous one
+pub struct Image<'a, F> {
+    data: &'a mut [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    format: PhantomData<F>,
+}
+
+impl<'a, F: PixelFormat> Image<'a, F> {
+    /// An image without padding between the rows
+    pub fn new(data: &'a mut [u8], width: usize, height: usize) -> Self {
+        Self::with_stride(data, width, height, width * F::SIZE)
+    }
+
+    /// # Panics
+    ///
+    /// If the stride is shorter than a row or the buffer is too small
+    pub fn with_stride(data: &'a mut [u8], width: usize, height: usize, stride: usize) -> Self {
+        assert!(width > 0 && stride >= width * F::SIZE);
+        assert!(data.len() >= stride * (height.max(1) - 1) + width * F::SIZE);
+        Self {
+            data,
+            width,
+            height,
+            stride,
+            format: PhantomData,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub(crate) fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        let row = self.width * F::SIZE;
+        self.data
+            .chunks(self.stride)
+            .take(self.height)
+            .map(move |r| &r[..row])
+    }
+
+    pub(crate) fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        let row = self.width * F::SIZE;
+        self.data
+            .chunks_mut(self.stride)
+            .take(self.height)
+            .map(move |r| &mut r[..row])
+    }
+
+    /// The rows for the workers that dither the pixels
+    pub(crate) fn rows_to_dither(&mut self) -> ImageRows<'_> {
+        ImageRows {
+            width: self.width,
+            pixels: self.rows_mut().map(PixelRow::Mut).collect(),
+            indices: Vec::new(),
+        }
+    }
+
+    /// The rows for the workers that only write the palette indices
+    pub(crate) fn rows_to_read(&self) -> ImageRows<'_> {
+        ImageRows {
+            width: self.width,
+            pixels: self.rows().map(PixelRow::Ref).collect(),
+            indices: Vec::new(),
+        }
+    }
+
+    /// Copies the colors of the pixels, row by row
+    pub fn read(&self) -> Vec<Color> {
+        self.rows()
+            .flat_map(|r| r.chunks_exact(F::SIZE))
+            .map(F::read)
+            .collect()
+    }
+
+    /// Stores the colors in the pixels, row by row
+    pub fn write(&mut self, colors: &[Color]) {
+        let pixels = self.rows_mut().flat_map(|r| r.chunks_exact_mut(F::SIZE));
+        for (pixel, &color) in pixels.zip(colors) {
+            F::write(pixel, color);
+        }
+    }
+}
+
+/// A row of pixels as the workers get it, either to dither it or only to read it
+pub(crate) enum PixelRow<'a> {
+    Mut(&'a mut [u8]),
+    Ref(&'a [u8]),
+}
+
+impl PixelRow<'_> {
+    pub fn get(&self) -> &[u8] {
+        match self {
+            Self::Mut(row) => row,
+            Self::Ref(row) => row,
+        }
+    }
+
+    /// Returns `None` if the pixels are only read
+    pub fn get_mut(&mut self) -> Option<&mut [u8]> {
+        match self {
+            Self::Mut(row) => Some(row),
+            Self::Ref(_) => None,
+        }
+    }
+
+    pub fn reborrow(&mut self) -> PixelRow<'_> {
+        match self {
+            Self::Mut(row) => PixelRow::Mut(row),
+            Self::Ref(row) => PixelRow::Ref(row),
+        }
+    }
+}
+
+/// The rows of an image as the workers get them, along with the rows of the
+/// palette indices they write, if any
+pub(crate) struct ImageRows<'a> {
+    pub width: usize,
+    pub pixels: Vec<PixelRow<'a>>,
+    /// Either a row of indices for each row of pixels or none at all
+    pub indices: Vec<IndexBuffer<'a>>,
+}
+
+impl<'a> ImageRows<'a> {
+    pub fn height(&self) -> usize {
+        self.pixels.len()
+    }
+
+    /// Whether the pixels are only read, the error then can't go into them
+    pub fn read_only(&self) -> bool {
+        self.pixels
+            .iter()
+            .any(|row| matches!(row, PixelRow::Ref(_)))
+    }
+
+    /// The workers also write the index of the palette color of every pixel
+    pub fn with_indices(self, indices: IndexBuffer<'a>) -> Self {
+        Self {
+            indices: indices.rows(self.width),
+            ..self
+        }
+    }
+}
+
+impl<'a> Image<'a, Rgb> {
+    /// Views the colors as an RGB image
+    pub fn from_colors(colors: &'a mut [Color], width: usize, height: usize) -> Self {
+        // Safe as `Color` is made of 3 bytes, with no padding
+        let len = colors.len() * 3;
+        let data = unsafe { std::slice::from_raw_parts_mut(colors.as_mut_ptr() as *mut u8, len) };
+        Self::new(data, width, height)
+    }
+}
+
+#[test]
+fn pixel_formats() {
+    fn round_trip<F: PixelFormat>(data: &mut [u8], width: usize, height: usize, stride: usize) {
+        let before = data.to_vec();
+        let mut image = Image::<F>::with_stride(data, width, height, stride);
+        let colors = image.read();
+        assert_eq!(colors.len(), width * height);
+        image.write(&colors);
+        assert_eq!(data, &before[..]);
+    }
+
+    // A 3x2 image with a byte of padding after each row
+    let mut bgra: Vec<u8> = (0..26).collect();
+    round_trip::<Bgra>(&mut bgra, 3, 2, 13);
+    let mut image = Image::<Bgra>::with_stride(&mut bgra, 3, 2, 13);
+    assert_eq!(image.read()[3], Color::new(15, 14, 13));
+    let mut colors = image.read();
+    colors[3].b = 0;
+    image.write(&colors);
+    assert_eq!(bgra[13..17], [0, 14, 15, 16]);
+    // The padding is never touched
+    let mut rgb: Vec<u8> = (0..20).collect();
+    round_trip::<Rgb>(&mut rgb, 3, 2, 10);
+    round_trip::<Rgb>(&mut rgb[..18], 3, 2, 9);
+    let mut gray = vec![7, 200, 13, 0];
+    round_trip::<Gray>(&mut gray, 2, 2, 2);
+
+    let mut gray = [0];
+    Image::<Gray>::new(&mut gray, 1, 1).write(&[Color::new(255, 0, 0)]);
+    assert_eq!(gray, [76]);
+}
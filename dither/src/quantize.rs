@@ -0,0 +1,599 @@
+//! Adaptive palettes, generated from the colors of an image
+//!
+//! The image is first reduced to a histogram with 5 bits per channel, which is
+//! built on the worker pool, then every quantizer works on the non-empty bins
+use super::blue_noise::XorShift;
+use super::color::{Color, Palette};
+use super::worker::WorkerPool;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Bits kept for each channel by the histogram
+const BITS: usize = 5;
+const SIDE: usize = 1 << BITS;
+
+/// Maximum number of k-means iterations
+const ITERATIONS: usize = 16;
+
+/// The colors closer than this (in 8-bit steps) to a locked color are left to
+/// it, the other palette colors are chosen among the rest
+const LOCKED_RADIUS: f64 = 16.0;
+
+/// The algorithm used to choose the colors of the palette
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quantizer {
+    /// Splits the box of the colors with the widest range at its median (P. Heckbert, 1982)
+    MedianCut,
+    /// Splits the boxes where the variance decreases the most (X. Wu, 1991)
+    Wu,
+    /// Merges the least used branches of the color octree (Gervautz and Purgathofer, 1988)
+    Octree,
+    /// Picks the colors with k-means++ and refines them with Lloyd's algorithm
+    KMeans,
+}
+
+impl Quantizer {
+    pub const ALL: [Self; 4] = [Self::MedianCut, Self::Wu, Self::Octree, Self::KMeans];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::MedianCut => "median-cut",
+            Self::Wu => "wu",
+            Self::Octree => "octree",
+            Self::KMeans => "k-means",
+        }
+    }
+
+    /// Generates a palette of at most `size` colors (less if the image doesn't
+    /// have enough of them) which starts with the `locked` ones
+    ///
+    /// The locked colors are kept as they are and the pixels that are close to
+    /// them are taken out of the histogram, so the remaining colors are chosen
+    /// for the rest of the image (k-means also uses them as fixed centroids)
+    ///
+    /// # Panics
+    ///
+    /// If `data` is empty or if there are more locked colors than `size`
+    pub fn generate(
+        self,
+        data: &[Color],
+        size: usize,
+        locked: &[Color],
+        pool: &WorkerPool,
+    ) -> Palette {
+        assert!(!data.is_empty() && locked.len() <= size);
+        let mut bins = histogram(data, pool);
+        let points: Vec<[f64; 3]> = locked.iter().map(|c| c.rgb().map(f64::from)).collect();
+        let near_locked = |color: [f64; 3]| {
+            !points.is_empty() && closest(&points, color).1 < LOCKED_RADIUS.powi(2)
+        };
+        for bin in bins.iter_mut().filter(|b| b.count > 0.0) {
+            if near_locked(bin.mean()) {
+                *bin = Bin::default();
+            }
+        }
+        let free = size - locked.len();
+        let mut colors: Vec<Color> = locked.to_vec();
+        let generated = match self {
+            _ if free == 0 || bins.iter().all(|b| b.count == 0.0) => Vec::new(),
+            Self::MedianCut => median_cut(bins, free),
+            Self::Wu => wu(&bins, free),
+            Self::Octree => octree(bins, free),
+            Self::KMeans => k_means(&bins, free, locked, pool),
+        };
+        // A box can still average to a color close to a locked one
+        for color in generated {
+            if !colors.contains(&color) && !near_locked(color.rgb().map(f64::from)) {
+                colors.push(color);
+            }
+        }
+        Palette::new(colors)
+    }
+}
+
+impl fmt::Display for Quantizer {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.name())
+    }
+}
+
+impl FromStr for Quantizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|q| q.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(Self::name).collect();
+                format!(
+                    "Unknown quantizer `{}`, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// The pixels that fall in a bin of the histogram (or in a group of them)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bin {
+    count: f64,
+    sum: [f64; 3],
+    // Sum of the squares of all the channels
+    squares: f64,
+}
+
+impl Bin {
+    fn add(&mut self, other: &Bin) {
+        self.count += other.count;
+        for (s, o) in self.sum.iter_mut().zip(other.sum) {
+            *s += o;
+        }
+        self.squares += other.squares;
+    }
+
+    fn mean(&self) -> [f64; 3] {
+        self.sum.map(|s| s / self.count)
+    }
+
+    fn color(&self) -> Color {
+        to_color(self.mean())
+    }
+}
+
+fn to_color(color: [f64; 3]) -> Color {
+    color.map(|c| c.round().clamp(0.0, 255.0) as u8).into()
+}
+
+fn index(color: Color) -> usize {
+    let [r, g, b] = color.rgb().map(|c| c as usize >> (8 - BITS));
+    (r * SIDE + g) * SIDE + b
+}
+
+/// Counts the colors of a part of the image
+pub struct HistogramWorker<'a> {
+    data: &'a [Color],
+    bins: &'a mut Vec<Bin>,
+}
+
+impl HistogramWorker<'_> {
+    pub fn run(&mut self) {
+        self.bins.resize(SIDE * SIDE * SIDE, Bin::default());
+        for &color in self.data {
+            let rgb = color.rgb().map(f64::from);
+            let bin = &mut self.bins[index(color)];
+            bin.add(&Bin {
+                count: 1.0,
+                sum: rgb,
+                squares: rgb.iter().map(|c| c * c).sum(),
+            });
+        }
+    }
+}
+
+/// Returns the histogram of the image, indexed like `index` does
+fn histogram(data: &[Color], pool: &WorkerPool) -> Vec<Bin> {
+    let threads = pool.threads().max(1);
+    let mut partials = vec![Vec::new(); threads];
+    pool.scope(|scope| {
+        let chunk = data.len().div_ceil(threads);
+        for (data, bins) in data.chunks(chunk).zip(partials.iter_mut()) {
+            scope.execute(HistogramWorker { data, bins });
+        }
+    });
+    let mut partials = partials.into_iter().filter(|p| !p.is_empty());
+    let mut bins = partials.next().unwrap();
+    for partial in partials {
+        for (bin, other) in bins.iter_mut().zip(&partial) {
+            bin.add(other);
+        }
+    }
+    bins
+}
+
+fn median_cut(bins: Vec<Bin>, size: usize) -> Vec<Color> {
+    let mut boxes = vec![bins
+        .into_iter()
+        .filter(|b| b.count > 0.0)
+        .collect::<Vec<_>>()];
+    // The widest channel of a box and its range
+    let widest = |bins: &[Bin]| {
+        (0..3)
+            .map(|c| {
+                let values = bins.iter().map(|b| b.mean()[c]);
+                let min = values.clone().fold(f64::INFINITY, f64::min);
+                (c, values.fold(f64::NEG_INFINITY, f64::max) - min)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+    };
+    while boxes.len() < size {
+        // The box to split is the one with the most pixels spread over the widest range
+        let next = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| (i, b.iter().map(|b| b.count).sum::<f64>() * widest(b).1))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        let Some((next, _)) = next else { break };
+        let channel = widest(&boxes[next]).0;
+        let bins = &mut boxes[next];
+        bins.sort_by(|a, b| a.mean()[channel].total_cmp(&b.mean()[channel]));
+        let half = bins.iter().map(|b| b.count).sum::<f64>() / 2.0;
+        let mut seen = 0.0;
+        let median = bins
+            .iter()
+            .position(|b| {
+                seen += b.count;
+                seen >= half
+            })
+            .unwrap();
+        let split = (median + 1).clamp(1, bins.len() - 1);
+        let upper = bins.split_off(split);
+        boxes.push(upper);
+    }
+    boxes
+        .iter()
+        .map(|bins| {
+            let mut total = Bin::default();
+            bins.iter().for_each(|b| total.add(b));
+            total.color()
+        })
+        .collect()
+}
+
+/// A box of the histogram for Wu's quantizer, the lower bounds are exclusive
+#[derive(Clone, Copy)]
+struct Cube {
+    lower: [usize; 3],
+    upper: [usize; 3],
+}
+
+/// Cumulative moments of the histogram, with a zero plane before each axis:
+/// count, sum of each channel and sum of the squares
+struct Moments {
+    moments: Vec<[f64; 5]>,
+}
+
+impl Moments {
+    const SIDE: usize = SIDE + 1;
+
+    fn new(bins: &[Bin]) -> Self {
+        let n = Self::SIDE;
+        let mut moments = vec![[0.0; 5]; n * n * n];
+        for (i, bin) in bins.iter().enumerate() {
+            let (r, g, b) = (i / SIDE / SIDE, i / SIDE % SIDE, i % SIDE);
+            let [sr, sg, sb] = bin.sum;
+            moments[((r + 1) * n + g + 1) * n + b + 1] = [bin.count, sr, sg, sb, bin.squares];
+        }
+        // Prefix sums along each axis
+        for axis in 0..3 {
+            let stride = [n * n, n, 1][axis];
+            for i in 0..moments.len() {
+                if i / stride % n > 0 {
+                    let previous = moments[i - stride];
+                    for (m, p) in moments[i].iter_mut().zip(previous) {
+                        *m += p;
+                    }
+                }
+            }
+        }
+        Self { moments }
+    }
+
+    fn at(&self, [r, g, b]: [usize; 3]) -> [f64; 5] {
+        let n = Self::SIDE;
+        self.moments[(r * n + g) * n + b]
+    }
+
+    /// Sum of the moments at the corners of the plane where `axis` is
+    /// `position`, with the signs used by the inclusion-exclusion of the cube
+    fn face(&self, cube: &Cube, axis: usize, position: usize) -> [f64; 5] {
+        let mut total = [0.0; 5];
+        for corner in 0..4 {
+            let mut point = cube.upper;
+            point[axis] = position;
+            let mut sign = 1.0;
+            for (bit, other) in (0..3).filter(|&a| a != axis).enumerate() {
+                if corner >> bit & 1 == 1 {
+                    point[other] = cube.lower[other];
+                    sign = -sign;
+                }
+            }
+            for (t, m) in total.iter_mut().zip(self.at(point)) {
+                *t += sign * m;
+            }
+        }
+        total
+    }
+
+    fn volume(&self, cube: &Cube) -> [f64; 5] {
+        let upper = self.face(cube, 0, cube.upper[0]);
+        let lower = self.face(cube, 0, cube.lower[0]);
+        [0, 1, 2, 3, 4].map(|i| upper[i] - lower[i])
+    }
+
+    fn variance(&self, cube: &Cube) -> f64 {
+        let [count, r, g, b, squares] = self.volume(cube);
+        if count > 0.0 {
+            squares - (r * r + g * g + b * b) / count
+        } else {
+            0.0
+        }
+    }
+
+    /// Finds the cut that maximizes the sum of the squared sums over the counts of
+    /// the two halves, which is the same as minimizing their total variance
+    fn maximize(&self, cube: &Cube, axis: usize, whole: [f64; 5]) -> Option<(f64, usize)> {
+        let lower = self.face(cube, axis, cube.lower[axis]);
+        let mut best = None;
+        for position in cube.lower[axis] + 1..cube.upper[axis] {
+            let face = self.face(cube, axis, position);
+            let half = [0, 1, 2, 3].map(|i| face[i] - lower[i]);
+            let other = [0, 1, 2, 3].map(|i| whole[i] - half[i]);
+            if half[0] <= 0.0 || other[0] <= 0.0 {
+                continue;
+            }
+            let score = |[count, r, g, b]: [f64; 4]| (r * r + g * g + b * b) / count;
+            let value = score(half) + score(other);
+            if best.is_none_or(|(v, _)| value > v) {
+                best = Some((value, position));
+            }
+        }
+        best
+    }
+
+    /// Splits the cube in two, returns `None` if it can't be split
+    fn cut(&self, cube: &mut Cube) -> Option<Cube> {
+        let whole = self.volume(cube);
+        let (_, axis, position) = (0..3)
+            .filter_map(|axis| {
+                self.maximize(cube, axis, whole)
+                    .map(|(value, position)| (value, axis, position))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0))?;
+        let mut other = *cube;
+        cube.upper[axis] = position;
+        other.lower[axis] = position;
+        Some(other)
+    }
+}
+
+fn wu(bins: &[Bin], size: usize) -> Vec<Color> {
+    let moments = Moments::new(bins);
+    let mut cubes = vec![Cube {
+        lower: [0; 3],
+        upper: [SIDE; 3],
+    }];
+    let mut variances = vec![moments.variance(&cubes[0])];
+    while cubes.len() < size {
+        let (next, &variance) = variances
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+        if variance <= 0.0 {
+            break;
+        }
+        match moments.cut(&mut cubes[next]) {
+            Some(other) => {
+                variances[next] = moments.variance(&cubes[next]);
+                variances.push(moments.variance(&other));
+                cubes.push(other);
+            }
+            // A single bin can't be split further
+            None => variances[next] = 0.0,
+        }
+    }
+    cubes
+        .iter()
+        .map(|cube| moments.volume(cube))
+        .filter(|v| v[0] > 0.0)
+        .map(|[count, r, g, b, _]| to_color([r / count, g / count, b / count]))
+        .collect()
+}
+
+fn octree(bins: Vec<Bin>, size: usize) -> Vec<Color> {
+    // The leaves along with their position at the current depth of the tree
+    let mut leaves: Vec<([usize; 3], Bin)> = bins
+        .into_iter()
+        .enumerate()
+        .filter(|(_, b)| b.count > 0.0)
+        .map(|(i, b)| ([i / SIDE / SIDE, i / SIDE % SIDE, i % SIDE], b))
+        .collect();
+    // Every level merges the children of the nodes with the fewest pixels
+    // until there are few enough leaves, or it moves to the level above
+    while leaves.len() > size {
+        let mut nodes: BTreeMap<[usize; 3], Vec<Bin>> = BTreeMap::new();
+        for (position, bin) in leaves.drain(..) {
+            nodes.entry(position.map(|p| p >> 1)).or_default().push(bin);
+        }
+        let mut nodes: Vec<_> = nodes.into_iter().collect();
+        nodes.sort_by(|a, b| {
+            let count = |bins: &[Bin]| bins.iter().map(|b| b.count).sum::<f64>();
+            count(&a.1).total_cmp(&count(&b.1))
+        });
+        let mut count: usize = nodes.iter().map(|n| n.1.len()).sum();
+        for (position, children) in nodes {
+            if count > size || children.len() == 1 {
+                count -= children.len() - 1;
+                let mut merged = Bin::default();
+                children.iter().for_each(|b| merged.add(b));
+                leaves.push((position, merged));
+            } else {
+                // The position doesn't matter anymore as this is the last level
+                leaves.extend(children.into_iter().map(|b| (position, b)));
+            }
+        }
+    }
+    leaves.iter().map(|(_, bin)| bin.color()).collect()
+}
+
+/// The pixels assigned to a k-means centroid
+#[derive(Clone, Copy, Default)]
+struct Cluster {
+    count: f64,
+    sum: [f64; 3],
+}
+
+/// Assigns a part of the bins to the closest centroid
+pub struct AssignWorker<'a> {
+    bins: &'a [Bin],
+    centroids: &'a [[f64; 3]],
+    clusters: &'a mut Vec<Cluster>,
+}
+
+impl AssignWorker<'_> {
+    pub fn run(&mut self) {
+        self.clusters.clear();
+        self.clusters
+            .resize(self.centroids.len(), Cluster::default());
+        for bin in self.bins {
+            let cluster = &mut self.clusters[closest(self.centroids, bin.mean()).0];
+            cluster.count += bin.count;
+            for (s, b) in cluster.sum.iter_mut().zip(bin.sum) {
+                *s += b;
+            }
+        }
+    }
+}
+
+/// The closest centroid and its squared distance
+fn closest(centroids: &[[f64; 3]], color: [f64; 3]) -> (usize, f64) {
+    centroids
+        .iter()
+        .map(|c| (0..3).map(|i| (c[i] - color[i]).powi(2)).sum::<f64>())
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap()
+}
+
+fn k_means(bins: &[Bin], size: usize, locked: &[Color], pool: &WorkerPool) -> Vec<Color> {
+    let bins: Vec<Bin> = bins.iter().filter(|b| b.count > 0.0).copied().collect();
+    let mut centroids: Vec<[f64; 3]> = locked.iter().map(|c| c.rgb().map(f64::from)).collect();
+    let fixed = centroids.len();
+
+    // k-means++: every new centroid is picked with a probability proportional
+    // to the number of pixels times their squared distance from the closest one
+    let mut rng = XorShift(0x2545_f491);
+    if centroids.is_empty() {
+        let first = bins
+            .iter()
+            .max_by(|a, b| a.count.total_cmp(&b.count))
+            .unwrap();
+        centroids.push(first.mean());
+    }
+    while centroids.len() < fixed + size.min(bins.len()) {
+        let weights: Vec<f64> = bins
+            .iter()
+            .map(|b| b.count * closest(&centroids, b.mean()).1)
+            .collect();
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            break;
+        }
+        let mut target = rng.next() as f64 / u32::MAX as f64 * total;
+        let chosen = weights
+            .iter()
+            .position(|&w| {
+                target -= w;
+                target <= 0.0
+            })
+            .unwrap_or(bins.len() - 1);
+        centroids.push(bins[chosen].mean());
+    }
+
+    // Lloyd's iterations, the assignment of the bins is split between the threads
+    let threads = pool.threads().max(1);
+    let chunk = bins.len().div_ceil(threads);
+    let mut partials = vec![Vec::new(); threads];
+    for _ in 0..ITERATIONS {
+        pool.scope(|scope| {
+            for (bins, clusters) in bins.chunks(chunk).zip(partials.iter_mut()) {
+                scope.execute(AssignWorker {
+                    bins,
+                    centroids: &centroids,
+                    clusters,
+                });
+            }
+        });
+        let mut moved = 0.0f64;
+        for (i, centroid) in centroids.iter_mut().enumerate().skip(fixed) {
+            let mut cluster = Cluster::default();
+            for partial in partials.iter().filter(|p| !p.is_empty()) {
+                cluster.count += partial[i].count;
+                for (s, p) in cluster.sum.iter_mut().zip(partial[i].sum) {
+                    *s += p;
+                }
+            }
+            if cluster.count > 0.0 {
+                let mean = cluster.sum.map(|s| s / cluster.count);
+                moved = moved.max(closest(&[mean], *centroid).1);
+                *centroid = mean;
+            }
+        }
+        // Less than half a step
+        if moved < 0.25 {
+            break;
+        }
+    }
+    centroids[fixed..].iter().map(|&c| to_color(c)).collect()
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn finds_the_colors() {
+    use super::worker::WorkerPool;
+
+    let rgb = [
+        [0u8, 0, 0],
+        [255, 255, 255],
+        [200, 30, 40],
+        [20, 100, 220],
+        [250, 200, 0],
+    ];
+    // Flat areas of different sizes
+    let data: Vec<Color> = (0..1000).map(|i| Color::from(rgb[i % 7 % 5])).collect();
+    let pool = WorkerPool::new(3);
+    for &quantizer in Quantizer::ALL.iter() {
+        let palette = quantizer.generate(&data, 5, &[], &pool);
+        assert_eq!(palette.colors().len(), 5, "{}", quantizer);
+        for color in rgb.iter() {
+            let color = Color::from(*color);
+            assert!(palette.colors().contains(&color), "{} {}", quantizer, color);
+        }
+
+        // With black locked only two colors can adapt
+        let black = [Color::from([0, 0, 0])];
+        let palette = quantizer.generate(&data[..], 3, &black, &pool);
+        assert!(palette.colors()[0] == black[0], "{}", quantizer);
+        assert!(palette.colors().len() <= 3, "{}", quantizer);
+
+        // The pixels close to the locked colors don't get colors of their own,
+        // all the others go to the rest of the image
+        let locked = [Color::from([0, 0, 0]), Color::from([255, 255, 255])];
+        let near = [[6u8, 8, 4], [248, 250, 244]];
+        let data: Vec<Color> = data
+            .iter()
+            .copied()
+            .chain((0..600).map(|i| Color::from(near[i % 2])))
+            .collect();
+        let palette = quantizer.generate(&data, 5, &locked, &pool);
+        assert_eq!(palette.colors()[..2], locked, "{}", quantizer);
+        for &color in palette.colors()[2..].iter() {
+            for lock in locked.iter() {
+                let distance: f64 = (0..3)
+                    .map(|i| (color.rgb()[i] as f64 - lock.rgb()[i] as f64).powi(2))
+                    .sum();
+                assert!(distance.sqrt() >= LOCKED_RADIUS, "{} {}", quantizer, color);
+            }
+        }
+        for color in rgb[2..].iter() {
+            let color = Color::from(*color);
+            assert!(palette.colors().contains(&color), "{} {}", quantizer, color);
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use super::indexed::IndexBuffer;
+use super::pixel::{ImageRows, PixelRow};
+use std::marker::PhantomData;
+
+// Under loom the synchronization goes through its primitives, so that the
+// tests at the bottom can check every interleaving of the threads
+#[cfg(loom)]
+use loom::{
+    hint,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::{Arc, Mutex},
+    thread::{self, Thread},
+};
+#[cfg(not(loom))]
+use std::{
+    hint,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::{Arc, Mutex},
+    thread::{self, Thread},
+};
+
+/// How many times a thread checks a counter before parking
+#[cfg(not(loom))]
+const SPINS: u32 = 64;
+/// Loom explores every spin, a single one is enough to cover both paths
+#[cfg(loom)]
+const SPINS: u32 = 1;
+
+/// How many more pixels a parked worker waits for
+const BATCH: usize = 64;
+
+/// The lowest bit of the state of a `Progress`, set while a thread is parked
+const WAITING: usize = 1;
+
+/// A counter that only grows, which a thread can wait on: it spins for a while
+/// and then it parks until the counter reaches the amount it needs
+///
+/// Only one thread at a time can wait on it
+struct Progress {
+    /// The counter shifted by one bit, next to the `WAITING` flag, they are
+    /// in the same atomic so that the thread that moves the counter and the
+    /// one that is about to park always agree on which one came first
+    state: AtomicUsize,
+    /// The amount the parked thread is waiting for
+    wanted: AtomicUsize,
+    waiter: Mutex<Option<Thread>>,
+}
+
+impl Progress {
+    fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            wanted: AtomicUsize::new(0),
+            waiter: Mutex::new(None),
+        }
+    }
+
+    fn get(&self) -> usize {
+        self.state.load(Ordering::Acquire) >> 1
+    }
+
+    /// Moves the counter to `amount`, if it's not already past it
+    fn set(&self, amount: usize) {
+        // Only one thread moves the counter, so it can't change in between
+        let done = self.get();
+        if amount > done {
+            self.add(amount - done);
+        }
+    }
+
+    /// Adds to the counter and returns its previous value
+    fn add(&self, amount: usize) -> usize {
+        let state = self.state.fetch_add(amount << 1, Ordering::AcqRel);
+        let previous = state >> 1;
+        // The waiter stores what it wants before setting the flag, which
+        // this has just read, thus it sees the right amount
+        if state & WAITING != 0 && previous + amount >= self.wanted.load(Ordering::Relaxed) {
+            if let Some(ref thread) = *self.waiter.lock().unwrap() {
+                thread.unpark();
+            }
+        }
+        previous
+    }
+
+    /// Waits until the counter reaches `amount` and returns its value, if the
+    /// thread gets parked it waits for `enough` instead, so that it doesn't
+    /// have to park again right away
+    fn wait(&self, amount: usize, enough: usize) -> usize {
+        for _ in 0..SPINS {
+            let done = self.get();
+            if done >= amount {
+                return done;
+            }
+            hint::spin_loop();
+        }
+        let amount = enough.max(amount);
+        *self.waiter.lock().unwrap() = Some(thread::current());
+        self.wanted.store(amount, Ordering::Relaxed);
+        // Either this sees the amount of the other thread, or the other
+        // thread sees the flag and unparks this one
+        let mut state = self.state.fetch_or(WAITING, Ordering::AcqRel);
+        loop {
+            if state >> 1 >= amount {
+                self.state.fetch_and(!WAITING, Ordering::Relaxed);
+                return state >> 1;
+            }
+            // It might also return because of an old unpark, thus the check again
+            thread::park();
+            state = self.state.load(Ordering::Acquire);
+        }
+    }
+}
+
+/// The first index of a row of an `IndexBuffer`
+enum Indices {
+    U8(*mut u8),
+    U16(*mut u16),
+}
+
+/// A row of pixels along with its indices and its progress
+struct Row {
+    /// The first byte of the row, it's only read when `writable` isn't set
+    pixels: *mut u8,
+    writable: bool,
+    indices: Option<Indices>,
+    progress: Progress,
+}
+
+impl Row {
+    fn new(pixels: PixelRow, indices: Option<IndexBuffer>) -> Self {
+        let (pixels, writable) = match pixels {
+            PixelRow::Mut(row) => (row.as_mut_ptr(), true),
+            // Never written, `pixel_mut` checks `writable`
+            PixelRow::Ref(row) => (row.as_ptr() as *mut u8, false),
+        };
+        Self {
+            pixels,
+            writable,
+            indices: indices.map(|indices| match indices {
+                IndexBuffer::U8(indices) => Indices::U8(indices.as_mut_ptr()),
+                IndexBuffer::U16(indices) => Indices::U16(indices.as_mut_ptr()),
+            }),
+            progress: Progress::new(),
+        }
+    }
+}
+
+// SAFETY: the pixels and the indices are only accessed through `SharedRows`,
+// which hands them out to a single worker at a time
+unsafe impl Send for Row {}
+unsafe impl Sync for Row {}
+
+/// The rows of an image shared between the diffusion workers, along with a
+/// cell for each one of their pixels and possibly their palette indices, each
+/// row has a counter of how many of its pixels have been processed, which the
+/// workers of the rows below follow to know which pixels and cells they can touch
+pub struct SharedRows<'a, T> {
+    ptr: *mut T,
+    width: usize,
+    /// Number of bytes of a pixel
+    size: usize,
+    rows: Arc<Vec<Row>>,
+    /// The first row that no worker has taken yet
+    next: Arc<AtomicUsize>,
+    _marker: PhantomData<(&'a mut [T], ImageRows<'a>)>,
+}
+
+impl<'a, T> SharedRows<'a, T> {
+    /// Every row holds pixels of the same size, with a cell for each one of
+    /// them
+    pub(crate) fn new(cells: &'a mut [T], rows: ImageRows<'a>) -> Self {
+        let width = rows.width;
+        assert_eq!(cells.len(), width * rows.height());
+        let size = rows.pixels.first().map_or(0, |row| row.get().len() / width);
+        assert!(rows
+            .pixels
+            .iter()
+            .all(|row| row.get().len() == width * size));
+        assert!(rows.indices.is_empty() || rows.indices.len() == rows.height());
+        assert!(rows.indices.iter().all(|row| row.len() == width));
+        let mut indices = rows.indices.into_iter();
+        let rows = rows
+            .pixels
+            .into_iter()
+            .map(|pixels| Row::new(pixels, indices.next()))
+            .collect();
+        Self {
+            ptr: cells.as_mut_ptr(),
+            width,
+            size,
+            rows: Arc::new(rows),
+            next: Arc::new(AtomicUsize::new(0)),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Takes the next row for the calling worker, each row is taken only once
+    /// and in order, it returns `None` once they have all been taken
+    pub fn take(&self) -> Option<usize> {
+        let y = self.next.fetch_add(1, Ordering::Relaxed);
+        if y < self.height() {
+            Some(y)
+        } else {
+            None
+        }
+    }
+
+    /// Waits until at least `amount` pixels of the row have been processed
+    /// and returns how many of them actually are, only the worker of the row
+    /// below can wait on a row
+    pub fn wait(&self, y: usize, amount: usize) -> usize {
+        self.rows[y]
+            .progress
+            .wait(amount, (amount + BATCH).min(self.width))
+    }
+
+    /// Marks the first `amount` pixels of the row as processed, making the
+    /// previous writes visible to whoever waits on it
+    pub fn advance(&self, y: usize, amount: usize) {
+        self.rows[y].progress.set(amount);
+    }
+
+    /// Returns the cell at the given position
+    ///
+    /// # Safety
+    ///
+    /// No other reference to the same cell must be alive, which the workers
+    /// guarantee by staying behind the progress of the row above
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_mut(&self, x: usize, y: usize) -> &'a mut T {
+        assert!(x < self.width && y < self.height());
+        &mut *self.ptr.add(y * self.width + x)
+    }
+
+    /// Returns the bytes of the pixel at the given position
+    ///
+    /// # Safety
+    ///
+    /// The same as `get_mut`, for the pixel
+    pub unsafe fn pixel(&self, x: usize, y: usize) -> &'a [u8] {
+        assert!(x < self.width && y < self.height());
+        std::slice::from_raw_parts(self.rows[y].pixels.add(x * self.size), self.size)
+    }
+
+    /// Same as `pixel` but it returns `None` if the pixels are only read
+    ///
+    /// # Safety
+    ///
+    /// The same as `get_mut`, for the pixel
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn pixel_mut(&self, x: usize, y: usize) -> Option<&'a mut [u8]> {
+        assert!(x < self.width && y < self.height());
+        let row = &self.rows[y];
+        row.writable
+            .then(|| std::slice::from_raw_parts_mut(row.pixels.add(x * self.size), self.size))
+    }
+
+    /// Writes the palette index of the pixel at the given position, if the rows
+    /// have indices
+    ///
+    /// # Safety
+    ///
+    /// The same as `get_mut`, for the index
+    pub unsafe fn set_index(&self, x: usize, y: usize, index: usize) {
+        assert!(x < self.width && y < self.height());
+        match self.rows[y].indices {
+            Some(Indices::U8(indices)) => *indices.add(x) = index as u8,
+            Some(Indices::U16(indices)) => *indices.add(x) = index as u16,
+            None => {}
+        }
+    }
+}
+
+impl<T> Clone for SharedRows<'_, T> {
+    fn clone(&self) -> Self {
+        Self {
+            ptr: self.ptr,
+            width: self.width,
+            size: self.size,
+            rows: Arc::clone(&self.rows),
+            next: Arc::clone(&self.next),
+            _marker: PhantomData,
+        }
+    }
+}
+
+unsafe impl<'a, T: Send> Send for SharedRows<'a, T> {}
+
+#[test]
+#[cfg(not(loom))]
+fn parked_wait() {
+    use std::time::Duration;
+
+    let mut data = vec![0u8; 200];
+    let rows = SharedRows::new(&mut data, no_pixels(100, 2));
+    let (amount, done) = std::thread::scope(|scope| {
+        let above = rows.clone();
+        scope.spawn(move || {
+            // Long enough for the other thread to stop spinning and park
+            for x in 1..=3 {
+                thread::sleep(Duration::from_millis(10));
+                above.advance(0, x * 10);
+            }
+            above.advance(0, 100);
+        });
+        (rows.wait(0, 30), rows.wait(0, 100))
+    });
+    assert!(amount >= 30);
+    assert_eq!(done, 100);
+}
+
+/// Rows without any bytes, for the tests that only use the cells
+#[cfg(test)]
+fn no_pixels<'a>(width: usize, height: usize) -> ImageRows<'a> {
+    ImageRows {
+        width,
+        pixels: (0..height).map(|_| PixelRow::Ref(&[])).collect(),
+        indices: Vec::new(),
+    }
+}
+
+/// Cells that loom tracks, so that it reports any access that isn't ordered
+/// by the progress of the rows, leaked since the loom threads need `'static` data
+#[cfg(all(test, loom))]
+fn tracked(len: usize) -> &'static mut [loom::cell::UnsafeCell<usize>] {
+    let cells: Vec<_> = (0..len).map(|_| loom::cell::UnsafeCell::new(0)).collect();
+    Box::leak(cells.into_boxed_slice())
+}
+
+/// `RUSTFLAGS="--cfg loom" cargo test -p dither --release loom_`
+#[test]
+#[cfg(loom)]
+fn loom_shared_rows() {
+    loom::model(|| {
+        // Two rows of two pixels, the second row follows the first one and
+        // adds to the pixel below each one of its own
+        let rows = SharedRows::new(tracked(4), no_pixels(2, 2));
+        let above = rows.clone();
+        let first = thread::spawn(move || {
+            for x in 0..2 {
+                unsafe { above.get_mut(x, 0) }.with_mut(|cell| unsafe { *cell = x + 1 });
+                unsafe { above.get_mut(x, 1) }.with_mut(|cell| unsafe { *cell += 10 });
+                above.advance(0, x + 1);
+            }
+        });
+        for x in 0..2 {
+            rows.wait(0, x + 1);
+            let value = unsafe { rows.get_mut(x, 0) }.with(|cell| unsafe { *cell });
+            unsafe { rows.get_mut(x, 1) }.with_mut(|cell| unsafe { *cell += value });
+            rows.advance(1, x + 1);
+        }
+        first.join().unwrap();
+        let row: Vec<_> = (0..2)
+            .map(|x| unsafe { rows.get_mut(x, 1) }.with(|cell| unsafe { *cell }))
+            .collect();
+        assert_eq!(row, [11, 12]);
+    });
+}
+
+/// `RUSTFLAGS="--cfg loom" cargo test -p dither --release loom_`
+#[test]
+#[cfg(loom)]
+fn loom_progress() {
+    loom::model(|| {
+        // The waiter parks for more than it needs, the counter gets there in
+        // two steps and neither the wake-up nor the value must get lost
+        let progress = Arc::new(Progress::new());
+        let counter = Arc::clone(&progress);
+        let adder = thread::spawn(move || {
+            assert_eq!(counter.add(1), 0);
+            counter.set(3);
+        });
+        assert!(progress.wait(1, 3) >= 1);
+        // It might park again, with the unpark of the first wait still pending
+        assert_eq!(progress.wait(3, 3), 3);
+        adder.join().unwrap();
+    });
+}
@@ -0,0 +1,136 @@
+//! Arbitrary closures executed by the `WorkerPool`, along with the handles to
+//! wait for their results
+use super::worker::{lock, Panics, WorkerPanic};
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+
+/// What a task shares with its handle
+struct Slot<T> {
+    /// The result of the task, which gets filled by the worker thread
+    result: Option<thread::Result<T>>,
+    /// Set when the handle gets dropped, as nobody would see the result
+    detached: bool,
+}
+
+type SharedSlot<T> = Arc<(Mutex<Slot<T>>, Condvar)>;
+
+/// A closure executed by the pool, it can run only once
+pub struct Task<'a> {
+    closure: Option<Box<dyn FnOnce() + Send + 'a>>,
+}
+
+impl<'a> Task<'a> {
+    /// Creates the task that runs `closure` and the handle that receives its
+    /// result, the panics nobody can see anymore go to `panics`
+    pub(crate) fn new<F, T>(closure: F, panics: &Panics) -> (Self, JoinHandle<'a, T>)
+    where
+        F: FnOnce() -> T + Send + 'a,
+        T: Send + 'a,
+    {
+        let slot: SharedSlot<T> = Arc::new((
+            Mutex::new(Slot {
+                result: None,
+                detached: false,
+            }),
+            Condvar::new(),
+        ));
+        let shared = Arc::clone(&slot);
+        let sink = Arc::clone(panics);
+        let task = Self {
+            closure: Some(Box::new(move || {
+                let value = panic::catch_unwind(AssertUnwindSafe(closure));
+                let (mutex, condvar) = shared.as_ref();
+                let mut slot = lock(mutex);
+                match value {
+                    Err(payload) if slot.detached => lock(&sink).push(payload),
+                    value => {
+                        slot.result = Some(value);
+                        condvar.notify_all();
+                    }
+                }
+            })),
+        };
+        let handle = JoinHandle {
+            slot,
+            panics: Arc::clone(panics),
+            scope: PhantomData,
+        };
+        (task, handle)
+    }
+
+    pub fn run(&mut self) {
+        if let Some(closure) = self.closure.take() {
+            closure()
+        }
+    }
+}
+
+/// Waits for the result of a task executed by the pool
+///
+/// If the handle gets dropped without joining a task that panicked, the panic
+/// goes to the scope, which reports it when it finishes, the handle can't
+/// outlive the scope so that nothing gets lost that way
+pub struct JoinHandle<'scope, T> {
+    slot: SharedSlot<T>,
+    panics: Panics,
+    scope: PhantomData<&'scope ()>,
+}
+
+impl<T> JoinHandle<'_, T> {
+    /// Whether the task has finished and `join` won't block
+    pub fn is_finished(&self) -> bool {
+        lock(&self.slot.0).result.is_some()
+    }
+
+    /// Blocks until the task has finished and returns its result, or the
+    /// payload of the panic if the closure panicked
+    pub fn join(self) -> Result<T, WorkerPanic> {
+        let (mutex, condvar) = self.slot.as_ref();
+        let mut slot = lock(mutex);
+        loop {
+            match slot.result.take() {
+                Some(value) => return value.map_err(WorkerPanic::from),
+                None => slot = condvar.wait(slot).unwrap_or_else(PoisonError::into_inner),
+            }
+        }
+    }
+}
+
+impl<T> Drop for JoinHandle<'_, T> {
+    fn drop(&mut self) {
+        let mut slot = lock(&self.slot.0);
+        slot.detached = true;
+        if let Some(Err(payload)) = slot.result.take() {
+            lock(&self.panics).push(payload);
+        }
+    }
+}
+
+#[test]
+fn spawned_tasks() {
+    use super::worker::WorkerPool;
+
+    let pool = WorkerPool::new(2);
+    let handles: Vec<_> = (0..8u64).map(|i| pool.spawn(move || i * i)).collect();
+    let squares: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    assert_eq!(squares, [0, 1, 4, 9, 16, 25, 36, 49]);
+
+    // In a scope the tasks can borrow the local data, even mutably
+    let mut data: Vec<u32> = (0..100).collect();
+    let total = pool.scope(|scope| {
+        let sums: Vec<_> = data
+            .chunks_mut(30)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk.iter_mut().for_each(|v| *v *= 2);
+                    chunk.iter().sum::<u32>()
+                })
+            })
+            .collect();
+        sums.into_iter().map(|h| h.join().unwrap()).sum::<u32>()
+    });
+    assert_eq!(total, 9900);
+    assert!(data.iter().enumerate().all(|(i, &v)| v as usize == i * 2));
+}
@@ -0,0 +1,137 @@
+//! Error diffusion that remembers the previous frame, so that the pixels that
+//! barely change keep their palette color instead of sparkling
+use super::cell::{Cell, FixedPixel};
+use super::color::{FixedColor, Palette};
+use super::pixel::PixelFormat;
+
+/// What happened to a pixel in the previous frame
+#[derive(Clone, Copy, Default)]
+pub struct History {
+    source: [u8; 3],
+    // The palette color it got, if any
+    index: Option<usize>,
+    // The error it received from its neighbours
+    error: FixedColor,
+}
+
+/// The state that is carried from one frame to the next
+pub struct Temporal {
+    threshold: u32,
+    width: usize,
+    height: usize,
+    history: Vec<History>,
+}
+
+impl Temporal {
+    /// The distance (in 8-bit steps) between black and white, no color can
+    /// change more than that so a larger threshold is clamped to it
+    pub const MAX_THRESHOLD: u32 = 442;
+
+    /// The pixels whose color changes less than `threshold` (in 8-bit steps)
+    /// since the previous frame are biased towards their previous palette color
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold: threshold.min(Self::MAX_THRESHOLD),
+            width: 0,
+            height: 0,
+            history: Vec::new(),
+        }
+    }
+
+    /// Forgets the previous frame, it must be called when the palette changes
+    pub fn reset(&mut self) {
+        self.history
+            .iter_mut()
+            .for_each(|h| *h = History::default());
+    }
+
+    /// Returns the history of every pixel, which is reset if the size of the
+    /// frame is not the same as the previous one
+    pub fn history(&mut self, width: usize, height: usize) -> &mut [History] {
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.history.clear();
+            self.history.resize(width * height, History::default());
+        }
+        &mut self.history
+    }
+
+    pub fn threshold(&self) -> u32 {
+        self.threshold
+    }
+}
+
+/// A pixel that keeps its previous palette color if it barely changed and the
+/// color is not much farther than the closest one, the error is kept in fixed
+/// point like `FixedPixel` does
+#[derive(Clone, Copy)]
+pub struct TemporalPixel {
+    error: FixedColor,
+    history: History,
+    threshold: u32,
+}
+
+impl TemporalPixel {
+    pub fn new(history: History, threshold: u32) -> Self {
+        Self {
+            error: FixedColor::default(),
+            history,
+            threshold: threshold.min(Temporal::MAX_THRESHOLD),
+        }
+    }
+
+    /// What happened to the pixel in this frame, for the next one
+    pub fn history(&self) -> History {
+        self.history
+    }
+}
+
+impl Cell for TemporalPixel {
+    type Error = FixedColor;
+
+    fn quantize<F: PixelFormat>(
+        &mut self,
+        pixel: &[u8],
+        error: FixedColor,
+        palette: &Palette,
+    ) -> (usize, FixedColor) {
+        let color = F::read(pixel);
+        let source = color.rgb();
+        let changed: u32 = (0..3)
+            .map(|i| (source[i] as i32 - self.history.source[i] as i32).pow(2) as u32)
+            .sum();
+        let previous = self
+            .history
+            .index
+            .filter(|&i| i < palette.colors().len() && changed <= self.threshold.pow(2));
+        let mut received = self.error + error;
+        if previous.is_some() {
+            // The error is averaged with the previous one, which keeps it from
+            // jumping around between the frames
+            received = FixedColor((received + self.history.error).0.map(|c| c / 2));
+        }
+        let value = FixedColor::from(color) + received;
+        let closest = palette.closest_fixed(value);
+        let distance = |i: usize| (value - FixedColor::from(palette.colors()[i])).length();
+        let margin = ((self.threshold as i64) << FixedColor::FRACTION_BITS).pow(2);
+        let index = match previous {
+            Some(previous) if distance(previous) <= distance(closest) + margin => previous,
+            _ => closest,
+        };
+        self.history = History {
+            source,
+            index: Some(index),
+            error: received,
+        };
+        (index, value - FixedColor::from(palette.colors()[index]))
+    }
+
+    fn diffuse<F: PixelFormat>(&mut self, _pixel: &mut [u8], error: FixedColor) {
+        self.error += error;
+    }
+
+    fn part(error: FixedColor, weight: i16, divisor: i16) -> FixedColor {
+        FixedPixel::part(error, weight, divisor)
+    }
+}
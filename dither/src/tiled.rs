@@ -0,0 +1,326 @@
+//! Approximate error diffusion, the image is split in horizontal tiles that
+//! are dithered independently, so they don't have to wait for each other
+use super::cell::Cell;
+use super::color::{Color, Palette};
+use super::indexed::IndexBuffer;
+use super::kernel::DiffusionKernel;
+use super::pixel::{Image, ImageRows, PixelFormat, PixelRow};
+use super::shared::SharedRows;
+use super::worker::{DiffusionWorker, WorkerPool};
+use std::fmt;
+use std::str::FromStr;
+
+/// How the image is split in tiles, the smaller they are the more of them can
+/// be dithered at the same time, but the more the result differs from the one
+/// of the whole image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tiling {
+    /// Number of rows of each tile
+    pub rows: usize,
+    /// Number of rows above each tile that are dithered with it, so that the
+    /// error gets to the levels it has in the middle of the image, the tile
+    /// above goes on in some of them to hide the boundary
+    pub seam: usize,
+}
+
+impl Tiling {
+    /// Tiles of the given height with a seam of a fourth of it
+    pub fn new(rows: usize) -> Self {
+        Self {
+            rows: rows.max(1),
+            seam: rows / 4,
+        }
+    }
+}
+
+impl FromStr for Tiling {
+    type Err = String;
+
+    /// Parses `<rows>` or `<rows>:<seam>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid tiles `{}`, expected <rows> or <rows>:<seam>", s);
+        let (rows, seam) = match s.split_once(':') {
+            Some((rows, seam)) => (rows, Some(seam)),
+            None => (s, None),
+        };
+        let rows: usize = rows.parse().map_err(|_| invalid())?;
+        let seam = match seam {
+            Some(seam) => seam.parse().map_err(|_| invalid())?,
+            None => Tiling::new(rows).seam,
+        };
+        if rows == 0 || seam > rows {
+            return Err(format!(
+                "The tiles must have at least a row and a seam not larger than them, not `{}`",
+                s
+            ));
+        }
+        Ok(Self { rows, seam })
+    }
+}
+
+impl fmt::Display for Tiling {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}:{}", self.rows, self.seam)
+    }
+}
+
+/// Error diffusion over independent tiles, each one goes to a worker along with
+/// the seam above it, then the seams are cut where the two tiles agree the most
+///
+/// The own rows of the tiles are dithered in place while the seams, which are
+/// also part of the tile above, are dithered on a copy of their pixels
+///
+/// # Panics
+///
+/// If there isn't a cell for each pixel
+pub fn diffuse_tiled<C: Cell + Clone + Send, F: PixelFormat>(
+    image: &mut Image<F>,
+    cells: &mut [C],
+    palette: &Palette,
+    kernel: DiffusionKernel,
+    serpentine: bool,
+    tiling: Tiling,
+    pool: &WorkerPool,
+) {
+    assert!(cells.len() == image.width() * image.height());
+    let rows = image.rows_to_dither();
+    diffuse_tiles::<C, F>(rows, cells, palette, kernel, serpentine, tiling, pool)
+}
+
+/// Same as `diffuse_tiled` for the rows the workers get, when they have
+/// indices the seams are cut where the indices agree
+pub(crate) fn diffuse_tiles<C: Cell + Clone + Send, F: PixelFormat>(
+    mut rows: ImageRows,
+    cells: &mut [C],
+    palette: &Palette,
+    kernel: DiffusionKernel,
+    serpentine: bool,
+    tiling: Tiling,
+    pool: &WorkerPool,
+) {
+    let (width, height) = (rows.width, rows.height());
+    let tile = tiling.rows.max(1);
+    let seam = tiling.seam.min(tile);
+    let indexed = !rows.indices.is_empty();
+    // The first row of each tile along with the first one of its seam
+    let tiles: Vec<_> = (0..height)
+        .step_by(tile)
+        .map(|y| (y, y.saturating_sub(seam)))
+        .collect();
+    let mut seams: Vec<Vec<u8>> = tiles
+        .iter()
+        .map(|&(y, start)| {
+            rows.pixels[start..y]
+                .iter()
+                .flat_map(|row| row.get())
+                .copied()
+                .collect()
+        })
+        .collect();
+    // The indices of the seams, `u16` is wide enough for any palette
+    let mut seam_indices: Vec<Vec<u16>> = tiles
+        .iter()
+        .map(|&(y, start)| vec![0; if indexed { (y - start) * width } else { 0 }])
+        .collect();
+    let mut copies: Vec<Vec<C>> = tiles
+        .iter()
+        .map(|&(y, start)| cells[start * width..(y + tile).min(height) * width].to_vec())
+        .collect();
+    {
+        let mut pixels = rows.pixels.iter_mut();
+        let mut indices = rows.indices.iter_mut();
+        pool.scope(|scope| {
+            let seams = seams.iter_mut().zip(&mut seam_indices);
+            for ((&(y, _), (seam, seam_indices)), copy) in tiles.iter().zip(seams).zip(&mut copies)
+            {
+                let count = (y + tile).min(height) - y;
+                let rows = ImageRows {
+                    width,
+                    pixels: seam
+                        .chunks_mut(width * F::SIZE)
+                        .map(PixelRow::Mut)
+                        .chain(pixels.by_ref().take(count).map(PixelRow::reborrow))
+                        .collect(),
+                    indices: IndexBuffer::U16(seam_indices)
+                        .rows(width)
+                        .into_iter()
+                        .chain(indices.by_ref().take(count).map(IndexBuffer::reborrow))
+                        .collect(),
+                };
+                let rows = SharedRows::new(copy, rows);
+                scope.execute(DiffusionWorker::<C, F>::new(
+                    rows, palette, kernel, serpentine,
+                ));
+            }
+        });
+    }
+    let size = F::SIZE;
+    let pixel = |x: usize| x * size..(x + 1) * size;
+    let seams = seams.iter().zip(&seam_indices);
+    for ((&(first, start), (seam, seam_indices)), copy) in tiles.iter().zip(seams).zip(&copies) {
+        let (overlap, own) = copy.split_at((first - start) * width);
+        // The tile above is already in the image
+        let cuts = cut(width, first - start, |x, y| {
+            if indexed {
+                rows.indices[start + y].get(x) != seam_indices[y * width + x] as usize
+            } else {
+                rows.pixels[start + y].get()[pixel(x)] != seam[y * width * size..][pixel(x)]
+            }
+        });
+        for (dy, row) in overlap.chunks_exact(width).enumerate() {
+            let y = start + dy;
+            for (x, (cell, &cut)) in row.iter().zip(&cuts).enumerate() {
+                if dy >= cut {
+                    if let Some(out) = rows.pixels[y].get_mut() {
+                        out[pixel(x)].copy_from_slice(&seam[dy * width * size..][pixel(x)]);
+                    }
+                    if indexed {
+                        rows.indices[y].set(x, seam_indices[dy * width + x] as usize);
+                    }
+                    cells[y * width + x] = cell.clone();
+                }
+            }
+        }
+        cells[first * width..first * width + own.len()].clone_from_slice(own);
+    }
+}
+
+/// Finds the row where each column of the seam goes from the tile above to the
+/// one below, it's the path across the image that goes through the pixels where
+/// the two tiles agree the most, moving by at most a row from a column to the
+/// next one, only in the lower half of the seam as the first rows of the tile
+/// below haven't received much error yet
+///
+/// `differs` tells whether the two tiles disagree on the pixel at the given
+/// column and row of the seam
+fn cut(width: usize, rows: usize, differs: impl Fn(usize, usize) -> bool) -> Vec<usize> {
+    if rows == 0 {
+        return vec![0; width];
+    }
+    let top = rows / 2;
+    let candidates = rows - top + 1;
+    // The cost of switching at row `top + r` of column `x`, the rows around
+    // the switch must agree, including the ones of the next columns
+    let cost = |x: usize, r: usize| -> u32 {
+        let y = top + r;
+        let columns = x.saturating_sub(1)..(x + 2).min(width);
+        let rows = y.saturating_sub(1)..(y + 1).min(rows);
+        columns
+            .flat_map(|x| rows.clone().map(move |y| (x, y)))
+            .filter(|&(x, y)| differs(x, y))
+            .count() as u32
+    };
+    // Lowest total cost of a path that ends at each row of the current column,
+    // and where it comes from
+    let mut totals: Vec<u32> = (0..candidates).map(|r| cost(0, r)).collect();
+    let mut from = vec![0u8; width * candidates];
+    for x in 1..width {
+        let previous = totals.clone();
+        for (r, total) in totals.iter_mut().enumerate() {
+            let (step, best) = (r.saturating_sub(1)..(r + 2).min(candidates))
+                .map(|p| (p, previous[p]))
+                .min_by_key(|&(_, total)| total)
+                .unwrap();
+            from[x * candidates + r] = (step + 1 - r) as u8;
+            *total = best + cost(x, r);
+        }
+    }
+    let mut r = (0..candidates).min_by_key(|&r| totals[r]).unwrap();
+    let mut cuts = vec![0; width];
+    for x in (0..width).rev() {
+        cuts[x] = top + r;
+        if x > 0 {
+            r = r + from[x * candidates + r] as usize - 1;
+        }
+    }
+    cuts
+}
+
+/// How much a dithered image differs from another one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Difference {
+    /// Fraction of the pixels that have a different color
+    pub mismatched: f32,
+    /// Average difference of the channels (in 8-bit steps) after blurring both
+    /// images, which is how the patterns look from afar
+    pub blurred: f32,
+}
+
+impl Difference {
+    /// Radius of the box blur
+    const RADIUS: usize = 4;
+
+    /// Compares two images of the same size, usually the approximate result
+    /// with the exact one
+    ///
+    /// # Panics
+    ///
+    /// If the images have a different number of pixels
+    pub fn between(a: &[Color], b: &[Color], width: usize) -> Self {
+        assert_eq!(a.len(), b.len());
+        if a.is_empty() {
+            return Self {
+                mismatched: 0.0,
+                blurred: 0.0,
+            };
+        }
+        let mismatched = a.iter().zip(b).filter(|(a, b)| a != b).count();
+        let (a, b) = (blur(a, width), blur(b, width));
+        let total: f32 = a
+            .iter()
+            .zip(&b)
+            .flat_map(|(a, b)| a.iter().zip(b).map(|(a, b)| (a - b).abs()))
+            .sum();
+        Self {
+            mismatched: mismatched as f32 / a.len() as f32,
+            blurred: total / (a.len() * 3) as f32,
+        }
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{:.1}% mismatched, {:.2} blurred",
+            self.mismatched * 100.0,
+            self.blurred
+        )
+    }
+}
+
+/// Averages each pixel with the ones around it, the window is cut at the edges
+fn blur(colors: &[Color], width: usize) -> Vec<[f32; 3]> {
+    let height = colors.len() / width;
+    let r = Difference::RADIUS;
+    // Summed area table, with an extra row and column of zeros
+    let mut sums = vec![[0u64; 3]; (width + 1) * (height + 1)];
+    for y in 0..height {
+        for x in 0..width {
+            let rgb = colors[y * width + x].rgb();
+            for c in 0..3 {
+                sums[(y + 1) * (width + 1) + x + 1][c] = rgb[c] as u64
+                    + sums[y * (width + 1) + x + 1][c]
+                    + sums[(y + 1) * (width + 1) + x][c]
+                    - sums[y * (width + 1) + x][c];
+            }
+        }
+    }
+    let mut blurred = Vec::with_capacity(colors.len());
+    for y in 0..height {
+        let (top, bottom) = (y.saturating_sub(r), (y + r + 1).min(height));
+        for x in 0..width {
+            let (left, right) = (x.saturating_sub(r), (x + r + 1).min(width));
+            let area = ((bottom - top) * (right - left)) as f32;
+            let at = |x: usize, y: usize| sums[y * (width + 1) + x];
+            let (br, tr, bl, tl) = (
+                at(right, bottom),
+                at(right, top),
+                at(left, bottom),
+                at(left, top),
+            );
+            blurred.push([0, 1, 2].map(|c| (br[c] + tl[c] - tr[c] - bl[c]) as f32 / area));
+        }
+    }
+    blurred
+}
@@ -0,0 +1,979 @@
+use super::builder::WorkerPoolBuilder;
+use super::cell::Cell;
+use super::color::Palette;
+use super::kernel::DiffusionKernel;
+use super::ordered::OrderedWorker;
+use super::pixel::PixelFormat;
+use super::quantize::{AssignWorker, HistogramWorker};
+use super::shared::SharedRows;
+use super::task::{JoinHandle, Task};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::mem::replace;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+/// A job that can be executed by the `WorkerPool`
+pub enum Worker<'a> {
+    /// A `DiffusionWorker`, boxed so that it can have any type of cells
+    Diffusion(Box<dyn FnMut() + Send + 'a>),
+    /// An `OrderedWorker`, boxed so that it can have any pixel format
+    Ordered(Box<dyn FnMut() + Send + 'a>),
+    Histogram(HistogramWorker<'a>),
+    Assign(AssignWorker<'a>),
+    Task(Task<'a>),
+}
+
+impl Worker<'_> {
+    pub fn run(&mut self) {
+        match self {
+            Self::Diffusion(worker) => worker(),
+            Self::Ordered(worker) => worker(),
+            Self::Histogram(worker) => worker.run(),
+            Self::Assign(worker) => worker.run(),
+            Self::Task(task) => task.run(),
+        }
+    }
+}
+
+impl<'a> Worker<'a> {
+    /// Runs and drops the worker behind the pointer of a `Job`, so that the
+    /// borrowed data is gone before it's marked as finished
+    ///
+    /// # Safety
+    ///
+    /// `worker` must come from the `Box<Worker<'a>>` of `Job::new` and it can
+    /// only be called once
+    unsafe fn run_erased(worker: *mut ()) {
+        Box::from_raw(worker.cast::<Self>()).run()
+    }
+}
+
+impl<'a, C: Cell + Send + 'a, F: PixelFormat + 'a> From<DiffusionWorker<'a, C, F>> for Worker<'a> {
+    fn from(mut worker: DiffusionWorker<'a, C, F>) -> Self {
+        Self::Diffusion(Box::new(move || worker.run()))
+    }
+}
+
+impl<'a, F: PixelFormat + 'a> From<OrderedWorker<'a, F>> for Worker<'a> {
+    fn from(mut worker: OrderedWorker<'a, F>) -> Self {
+        Self::Ordered(Box::new(move || worker.run()))
+    }
+}
+
+impl<'a> From<HistogramWorker<'a>> for Worker<'a> {
+    fn from(worker: HistogramWorker<'a>) -> Self {
+        Self::Histogram(worker)
+    }
+}
+
+impl<'a> From<AssignWorker<'a>> for Worker<'a> {
+    fn from(worker: AssignWorker<'a>) -> Self {
+        Self::Assign(worker)
+    }
+}
+
+impl<'a> From<Task<'a>> for Worker<'a> {
+    fn from(task: Task<'a>) -> Self {
+        Self::Task(task)
+    }
+}
+
+/// Error diffusion worker, it takes the rows that nobody has taken yet one at
+/// a time, each one following the progress of the row above it, the pixels
+/// are stored as `F`
+pub struct DiffusionWorker<'a, C, F> {
+    palette: &'a Palette,
+    kernel: DiffusionKernel,
+    rows: SharedRows<'a, C>,
+    serpentine: bool,
+    format: PhantomData<fn() -> F>,
+}
+
+impl<'a, C: Cell, F: PixelFormat> DiffusionWorker<'a, C, F> {
+    /// Creates a worker for the rows, more of them can share the same rows
+    /// and any number of them gets the image done, even a single one
+    ///
+    /// If `serpentine` is set the odd rows are processed from right to left,
+    /// with the kernel mirrored
+    pub(crate) fn new(
+        rows: SharedRows<'a, C>,
+        palette: &'a Palette,
+        kernel: DiffusionKernel,
+        serpentine: bool,
+    ) -> Self {
+        Self {
+            palette,
+            kernel,
+            rows,
+            serpentine,
+            format: PhantomData,
+        }
+    }
+
+    pub fn run(&mut self) {
+        let width = self.rows.width();
+        let lag = self.kernel.lag();
+        // The rows are taken in order, thus the row above is always either
+        // done or being processed by a running worker
+        while let Some(y) = self.rows.take() {
+            let _abandon = Abandon {
+                rows: &self.rows,
+                y,
+            };
+            let reversed = self.serpentine && y % 2 == 1;
+            // The error that goes to the pixels that follow the current one
+            let mut ahead = vec![C::Error::default(); self.kernel.ahead()];
+            // Number of pixels of this row that have received all their error
+            let mut ready = if y == 0 { width } else { 0 };
+            for position in 0..width {
+                if position >= ready {
+                    // When the row above goes in the other direction its last
+                    // pixels are the first ones of this row
+                    let needed = if self.serpentine {
+                        width
+                    } else {
+                        (position + lag + 1).min(width)
+                    };
+                    let done = self.rows.wait(y - 1, needed);
+                    ready = if done == width { width } else { done - lag };
+                }
+                let x = if reversed {
+                    width - 1 - position
+                } else {
+                    position
+                };
+                let mut error = C::Error::default();
+                if let Some(&carried) = ahead.first() {
+                    error = carried;
+                    ahead.rotate_left(1);
+                    *ahead.last_mut().unwrap() = C::Error::default();
+                }
+                // SAFETY: the row above is far enough ahead that nobody else touches this pixel
+                let pixel = unsafe { self.rows.pixel(x, y) };
+                match self.transparent(pixel) {
+                    // The error the pixel received is dropped
+                    Some(index) => unsafe { self.rows.set_index(x, y, index) },
+                    None => {
+                        let cell = unsafe { self.rows.get_mut(x, y) };
+                        let (index, new_error) = cell.quantize::<F>(pixel, error, self.palette);
+                        unsafe {
+                            if let Some(pixel) = self.rows.pixel_mut(x, y) {
+                                F::write(pixel, self.palette.colors()[index]);
+                            }
+                            self.rows.set_index(x, y, index);
+                        }
+                        self.diffuse_error(&mut ahead, new_error, (position, y), reversed);
+                    }
+                }
+                self.rows.advance(y, position + 1);
+            }
+        }
+    }
+
+    /// The transparent entry of the palette if it has one and the pixel is
+    /// transparent, such a pixel is left out of the error diffusion
+    fn transparent(&self, pixel: &[u8]) -> Option<usize> {
+        self.palette.transparent().filter(|_| F::transparent(pixel))
+    }
+
+    /// Spreads the error using the weights of the kernel, the part that goes
+    /// to the current row is accumulated in `ahead` to be added when the
+    /// pixels are processed
+    ///
+    /// The position is the number of pixels processed before the current one,
+    /// thus when `reversed` is set it starts from the right end of the row
+    fn diffuse_error(
+        &self,
+        ahead: &mut [C::Error],
+        error: C::Error,
+        (position, y): (usize, usize),
+        reversed: bool,
+    ) {
+        let (kernel, rows) = (&self.kernel, &self.rows);
+        let (width, height) = (rows.width(), rows.height());
+        for &(dx, dy, weight) in kernel.weights() {
+            // Mirroring the kernel is the same as going forward from the other end
+            let x = position as isize + dx;
+            if x < 0 || x >= width as isize || y + dy >= height {
+                continue;
+            }
+            let x = if reversed {
+                width - 1 - x as usize
+            } else {
+                x as usize
+            };
+            let part = C::part(error, weight, kernel.divisor());
+            if dy == 0 {
+                ahead[dx as usize - 1] += part;
+            } else {
+                // SAFETY: the lag of the kernel keeps the workers of the rows
+                // below away from the pixels this one writes on
+                if self.transparent(unsafe { rows.pixel(x, y + dy) }).is_some() {
+                    continue;
+                }
+                let (cell, pixel) = unsafe { (rows.get_mut(x, y + dy), rows.pixel_mut(x, y + dy)) };
+                // The pixels that are only read go to the cells that keep the
+                // error themselves, the empty pixel is never touched
+                cell.diffuse::<F>(pixel.unwrap_or_default(), part);
+            }
+        }
+    }
+}
+
+/// Marks the row of a diffusion worker as done if it panics, otherwise the
+/// worker of the row below would wait for it forever
+struct Abandon<'r, 'a, C> {
+    rows: &'r SharedRows<'a, C>,
+    y: usize,
+}
+
+impl<C> Drop for Abandon<'_, '_, C> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.rows.advance(self.y, self.rows.width());
+        }
+    }
+}
+
+/// The jobs waiting for a thread, in the order they have been executed
+struct Queue {
+    jobs: VecDeque<Job>,
+    /// Set when the pool gets dropped, the threads exit once the queue is empty
+    closed: bool,
+}
+
+/// What the `WorkerPool` shares with its threads
+struct Shared {
+    queue: Mutex<Queue>,
+    /// Signaled when a job gets queued or the pool gets closed
+    queued: Condvar,
+    /// The scope of the workers executed directly on the pool
+    root: Arc<ScopeData>,
+}
+
+/// The payloads of the workers that panicked, which wait for the scope
+pub(crate) type Panics = Arc<Mutex<Vec<Box<dyn Any + Send>>>>;
+
+/// Locks the mutex even if a thread panicked while holding it, all the data
+/// behind the mutexes of the pool is still valid in that case
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// What the threads report to the scope that executed a worker
+struct ScopeData {
+    /// Number of workers of the scope that haven't finished yet
+    pending: Mutex<usize>,
+    /// Signaled when `pending` gets to zero
+    done: Condvar,
+    panics: Panics,
+}
+
+impl ScopeData {
+    fn new() -> Self {
+        Self {
+            pending: Mutex::new(0),
+            done: Condvar::new(),
+            panics: Panics::default(),
+        }
+    }
+
+    fn finished(&self) {
+        let mut pending = lock(&self.pending);
+        *pending -= 1;
+        if *pending == 0 {
+            self.done.notify_all();
+        }
+    }
+
+    /// Sleeps until all the workers of the scope have finished
+    fn wait(&self) {
+        let mut pending = lock(&self.pending);
+        while *pending > 0 {
+            pending = self
+                .done
+                .wait(pending)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Takes the panics that happened since the last time they were taken
+    fn take_panics(&self) -> Result<(), WorkerPanic> {
+        let payloads = std::mem::take(&mut *lock(&self.panics));
+        if payloads.is_empty() {
+            Ok(())
+        } else {
+            Err(WorkerPanic { payloads })
+        }
+    }
+}
+
+/// A queued worker with its type erased, the thread that takes it calls `run`
+/// on the pointer, which rebuilds the box with the right lifetime
+struct Job {
+    worker: *mut (),
+    run: unsafe fn(*mut ()),
+    scope: Arc<ScopeData>,
+}
+
+// SAFETY: `worker` points to a `Worker`, which is `Send`
+unsafe impl Send for Job {}
+
+impl Job {
+    /// Boxes the worker and counts it in the scope
+    ///
+    /// # Safety
+    ///
+    /// The job has to run before `'a` ends
+    unsafe fn new<'a>(worker: Worker<'a>, scope: &Arc<ScopeData>) -> Self {
+        *lock(&scope.pending) += 1;
+        Self {
+            worker: Box::into_raw(Box::new(worker)).cast(),
+            run: Worker::<'a>::run_erased,
+            scope: Arc::clone(scope),
+        }
+    }
+
+    /// Runs the worker and reports to its scope, a panic in the drop of the
+    /// worker is caught too, thus the thread that runs it never dies
+    fn execute(self) {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            // SAFETY: the job is consumed, so it runs only once, and its
+            // scope is waiting for it
+            unsafe { (self.run)(self.worker) }
+        }));
+        if let Err(payload) = result {
+            lock(&self.scope.panics).push(payload);
+        }
+        self.scope.finished();
+    }
+}
+
+impl Shared {
+    /// Takes the first job of the queue, sleeping until there is one, or
+    /// returns `None` if the thread has to exit
+    fn next(&self) -> Option<Job> {
+        let mut queue = lock(&self.queue);
+        loop {
+            if let Some(job) = queue.jobs.pop_front() {
+                return Some(job);
+            }
+            if queue.closed {
+                return None;
+            }
+            queue = self
+                .queued
+                .wait(queue)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// The loop of each thread of the pool
+    fn work(&self) {
+        while let Some(job) = self.next() {
+            job.execute();
+        }
+    }
+
+    /// Runs the queued jobs of the scope on the current thread and then waits
+    /// for those that other threads have already taken, so that a scope
+    /// opened by a job of the same pool doesn't wait for a thread that is
+    /// never going to be free
+    fn help(&self, scope: &Arc<ScopeData>) {
+        loop {
+            let job = {
+                let mut queue = lock(&self.queue);
+                let position = queue
+                    .jobs
+                    .iter()
+                    .position(|job| Arc::ptr_eq(&job.scope, scope));
+                position.and_then(|i| queue.jobs.remove(i))
+            };
+            match job {
+                Some(job) => job.execute(),
+                None => break,
+            }
+        }
+        scope.wait();
+    }
+}
+
+/// One or more workers panicked, it holds their payloads
+pub struct WorkerPanic {
+    payloads: Vec<Box<dyn Any + Send>>,
+}
+
+impl WorkerPanic {
+    pub fn payloads(&self) -> &[Box<dyn Any + Send>] {
+        &self.payloads
+    }
+
+    pub fn into_payloads(self) -> Vec<Box<dyn Any + Send>> {
+        self.payloads
+    }
+
+    /// The messages of the panics, if they have one
+    fn messages(&self) -> impl Iterator<Item = &str> {
+        self.payloads.iter().map(|payload| {
+            if let Some(message) = payload.downcast_ref::<&str>() {
+                message
+            } else if let Some(message) = payload.downcast_ref::<String>() {
+                message
+            } else {
+                "Box<dyn Any>"
+            }
+        })
+    }
+}
+
+impl From<Box<dyn Any + Send>> for WorkerPanic {
+    fn from(payload: Box<dyn Any + Send>) -> Self {
+        Self {
+            payloads: vec![payload],
+        }
+    }
+}
+
+impl fmt::Debug for WorkerPanic {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_list().entries(self.messages()).finish()
+    }
+}
+
+impl fmt::Display for WorkerPanic {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let messages: Vec<_> = self.messages().collect();
+        write!(fmt, "A worker panicked: {}", messages.join(", "))
+    }
+}
+
+impl Error for WorkerPanic {}
+
+/// A fixed number of threads that take the workers from a shared queue, so
+/// that executing one never waits for a thread to be free
+///
+/// The workers executed directly on the pool can't borrow anything, those
+/// that do have to be executed in a `scope`
+pub struct WorkerPool {
+    handles: Mutex<Vec<thread::JoinHandle<()>>>,
+    shared: Arc<Shared>,
+    config: WorkerPoolBuilder,
+}
+
+impl WorkerPool {
+    /// Creates a pool with the given number of threads and the default
+    /// configuration of the `WorkerPoolBuilder`
+    ///
+    /// # Panics
+    ///
+    /// If `workers` is zero or if the threads can't be spawned
+    pub fn new(workers: usize) -> Self {
+        WorkerPoolBuilder::new()
+            .threads(workers)
+            .build()
+            .expect("Failed to create the worker pool")
+    }
+
+    /// The configuration of a pool, which by default has a thread for each CPU
+    pub fn builder() -> WorkerPoolBuilder {
+        WorkerPoolBuilder::new()
+    }
+
+    pub(crate) fn with_builder(config: WorkerPoolBuilder) -> io::Result<Self> {
+        let workers = config.thread_count();
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                jobs: VecDeque::new(),
+                closed: false,
+            }),
+            queued: Condvar::new(),
+            root: Arc::new(ScopeData::new()),
+        });
+        let pool = Self {
+            handles: Mutex::new(Vec::with_capacity(workers)),
+            shared,
+            config,
+        };
+        for index in 0..workers {
+            let handle = pool.spawn_thread(index)?;
+            lock(&pool.handles).push(handle);
+        }
+        Ok(pool)
+    }
+
+    fn spawn_thread(&self, index: usize) -> io::Result<thread::JoinHandle<()>> {
+        let shared = Arc::clone(&self.shared);
+        self.config.spawn(index, move || shared.work())
+    }
+
+    /// Runs the closure with a scope in which the workers can borrow anything
+    /// that outlives the call, as it returns only once they have all finished,
+    /// even if the closure panics
+    ///
+    /// Meanwhile the current thread runs the workers of the scope that are
+    /// still queued, thus a scope can be opened from a worker of the same pool
+    ///
+    /// # Panics
+    ///
+    /// If the closure panics, or with a `WorkerPanic` if any of the workers
+    /// panicked, `try_scope` returns it instead
+    pub fn scope<'env, F, R>(&self, closure: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        match self.try_scope(closure) {
+            Ok(value) => value,
+            Err(panic) => panic::resume_unwind(Box::new(panic)),
+        }
+    }
+
+    /// Same as `scope`, but if any of the workers panicked their payloads are
+    /// returned instead of resuming the panic
+    pub fn try_scope<'env, F, R>(&self, closure: F) -> Result<R, WorkerPanic>
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        self.respawn();
+        let scope = Scope {
+            pool: self,
+            data: Arc::new(ScopeData::new()),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| closure(&scope)));
+        self.shared.help(&scope.data);
+        match result {
+            Ok(value) => scope.data.take_panics().map(|()| value),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    /// Sleeps until all the workers executed directly on the pool have finished
+    pub fn wait_all(&self) {
+        self.shared.root.wait();
+    }
+
+    pub fn threads(&self) -> usize {
+        lock(&self.handles).len()
+    }
+
+    /// Number of workers that are waiting for a thread
+    pub fn pending(&self) -> usize {
+        lock(&self.shared.queue).jobs.len()
+    }
+
+    /// Replaces the threads that died with new ones, the workers catch their
+    /// panics so this should never happen
+    fn respawn(&self) {
+        let mut handles = lock(&self.handles);
+        for (i, slot) in handles.iter_mut().enumerate() {
+            if slot.is_finished() {
+                let handle = self
+                    .spawn_thread(i)
+                    .expect("Failed to replace a worker thread");
+                let dead = replace(slot, handle);
+                if let Err(payload) = dead.join() {
+                    lock(&self.shared.root.panics).push(payload);
+                }
+            }
+        }
+    }
+
+    /// Waits for all the workers executed directly on the pool and returns
+    /// the panics that happened since the last time they were taken
+    pub fn finish(&self) -> Result<(), WorkerPanic> {
+        self.wait_all();
+        self.respawn();
+        self.shared.root.take_panics()
+    }
+
+    /// Puts the worker at the end of the queue and wakes up a thread
+    ///
+    /// # Safety
+    ///
+    /// The worker has to finish before `'a` ends, which is the case if `scope`
+    /// is waited for before that
+    unsafe fn queue<'a>(&self, worker: Worker<'a>, scope: &Arc<ScopeData>) {
+        assert!(self.threads() > 0, "The pool has no threads");
+        let job = Job::new(worker, scope);
+        lock(&self.shared.queue).jobs.push_back(job);
+        self.shared.queued.notify_one();
+    }
+
+    /// Puts the worker at the end of the queue and wakes up a thread, if they
+    /// are all busy it runs as soon as one of them is done with what comes before
+    ///
+    /// # Panics
+    ///
+    /// If the pool has no threads, as nobody could execute the worker
+    pub(crate) fn execute(&self, worker: impl Into<Worker<'static>>) {
+        // SAFETY: the worker doesn't borrow anything
+        unsafe { self.queue(worker.into(), &self.shared.root) }
+    }
+
+    /// Runs the closure on one of the threads, its result can be taken with
+    /// the returned handle, if it panics and the handle gets dropped the panic
+    /// is returned by `finish`
+    pub fn spawn<F, T>(&self, closure: F) -> JoinHandle<'static, T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (task, handle) = Task::new(closure, &self.shared.root.panics);
+        self.execute(task);
+        handle
+    }
+}
+
+impl Drop for WorkerPool {
+    /// Lets the threads run what is left in the queue and joins them
+    fn drop(&mut self) {
+        lock(&self.shared.queue).closed = true;
+        self.shared.queued.notify_all();
+        for handle in lock(&self.handles).drain(..) {
+            // The panics of the workers have already been caught
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The workers executed in a scope can borrow anything that lives for `'env`,
+/// it's only reachable from the closure of `WorkerPool::scope`, which waits
+/// for all of them before returning
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope WorkerPool,
+    data: Arc<ScopeData>,
+    /// Both lifetimes are invariant, as in `std::thread::Scope`, so that the
+    /// workers can't borrow anything shorter
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope> Scope<'scope, '_> {
+    pub fn threads(&self) -> usize {
+        self.pool.threads()
+    }
+
+    /// Same as `WorkerPool::execute` but the worker can borrow anything that
+    /// outlives the scope
+    pub(crate) fn execute(&'scope self, worker: impl Into<Worker<'scope>>) {
+        // SAFETY: `WorkerPool::try_scope` waits for the worker before 'scope ends
+        unsafe { self.pool.queue(worker.into(), &self.data) }
+    }
+
+    /// Runs the worker on the current thread, if it panics the payload goes to
+    /// the scope as with the workers run by the threads of the pool
+    pub(crate) fn run(&'scope self, worker: impl Into<Worker<'scope>>) {
+        // SAFETY: the worker is done before this returns
+        unsafe { Job::new(worker.into(), &self.data) }.execute();
+    }
+
+    /// Same as `WorkerPool::spawn` but the closure can borrow anything that
+    /// outlives the scope
+    pub fn spawn<F, T>(&'scope self, closure: F) -> JoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let (task, handle) = Task::new(closure, &self.data.panics);
+        self.execute(task);
+        handle
+    }
+}
+
+#[test]
+fn queued_workers() {
+    use std::sync::mpsc;
+
+    // The first task blocks the only thread, the others queue up behind it
+    // and `spawn` must return right away for all of them
+    let pool = WorkerPool::new(1);
+    let (sender, receiver) = mpsc::channel::<()>();
+    let blocked = pool.spawn(move || receiver.recv().unwrap());
+    let handles: Vec<_> = (0..100u32).map(|i| pool.spawn(move || i * 2)).collect();
+    assert!(pool.pending() >= 100);
+    sender.send(()).unwrap();
+    blocked.join().unwrap();
+    let sum: u32 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    assert_eq!(sum, 9900);
+    assert!(pool.finish().is_ok());
+    assert_eq!(pool.pending(), 0);
+}
+
+/// The workers borrow from the stack through the type erased jobs of the
+/// queue, which is what Miri checks here, with sizes small enough for it
+///
+/// `cargo +nightly miri test -p dither`
+#[test]
+fn borrowed_workers() {
+    use super::cell::FixedPixel;
+    use super::color::Color;
+    use super::kernel::DiffusionKernel;
+    use super::pixel::{Image, Rgb};
+
+    let palette = Palette::new(vec![Color::new(0, 0, 0), Color::new(255, 255, 255)]);
+    let pool = WorkerPool::new(2);
+    for _ in 0..2 {
+        let mut data: Vec<u8> = (0..24u8).flat_map(|i| [i * 10; 3]).collect();
+        let mut image = Image::<Rgb>::new(&mut data, 4, 6);
+        let mut cells = vec![FixedPixel::default(); 24];
+        let mut totals = [0u32; 3];
+        pool.scope(|scope| {
+            // More tasks than threads, each one writing on its own element
+            for (i, total) in totals.iter_mut().enumerate() {
+                drop(scope.spawn(move || *total = i as u32 + 1));
+            }
+            // The same path as `dither`, part of the rows on the calling thread
+            let kernel = DiffusionKernel::FLOYD_STEINBERG;
+            super::diffuse(&mut image, &mut cells, &palette, kernel, false, scope);
+        });
+        assert!(image.read().iter().all(|&c| palette.find(c).is_some()));
+        assert_eq!(totals, [1, 2, 3]);
+        // The workers that only read the pixels and write the indices
+        let mut indices = [0u8; 24];
+        super::dither_indices(&image, &palette, &Default::default(), &mut indices, &pool);
+        assert!(image
+            .read()
+            .iter()
+            .zip(&indices)
+            .all(|(&c, &i)| palette.colors()[i as usize] == c));
+    }
+    // The tasks still queued when the pool gets dropped run before the threads exit
+    let counter = Arc::new(Mutex::new(0));
+    let pool = WorkerPool::new(1);
+    for _ in 0..4 {
+        let counter = Arc::clone(&counter);
+        drop(pool.spawn(move || *lock(&counter) += 1));
+    }
+    drop(pool);
+    assert_eq!(*lock(&counter), 4);
+}
+
+/// The scheduler the pool had before the queue, each thread holds a single
+/// worker and `execute` spins over them until it finds an idle one, kept to
+/// compare the two in the benchmarks
+#[cfg(test)]
+type SpinningThread = (
+    Arc<(
+        Mutex<Option<Worker<'static>>>,
+        std::sync::atomic::AtomicBool,
+    )>,
+    thread::JoinHandle<()>,
+);
+
+#[cfg(test)]
+struct SpinningPool {
+    threads: Vec<SpinningThread>,
+}
+
+#[cfg(test)]
+impl SpinningPool {
+    fn new(workers: usize) -> Self {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let threads = (0..workers)
+            .map(|_| {
+                let shared =
+                    Arc::new((Mutex::new(None::<Worker<'static>>), AtomicBool::new(false)));
+                let cloned = Arc::clone(&shared);
+                let handle = thread::spawn(move || loop {
+                    while !cloned.1.load(Ordering::Acquire) {
+                        thread::park();
+                    }
+                    let mut worker = lock(&cloned.0);
+                    match worker.as_mut() {
+                        Some(worker) => worker.run(),
+                        None => return,
+                    }
+                    *worker = None;
+                    cloned.1.store(false, Ordering::Release);
+                });
+                (shared, handle)
+            })
+            .collect();
+        Self { threads }
+    }
+
+    fn execute(&self, worker: Worker<'static>) {
+        use std::sync::atomic::Ordering;
+
+        let (shared, handle) = self
+            .threads
+            .iter()
+            .cycle()
+            .find(|(shared, _)| !shared.1.load(Ordering::Acquire))
+            .unwrap();
+        *lock(&shared.0) = Some(worker);
+        shared.1.store(true, Ordering::Release);
+        handle.thread().unpark();
+    }
+
+    fn wait_all(&self) {
+        use std::sync::atomic::Ordering;
+
+        for (shared, _) in &self.threads {
+            while shared.1.load(Ordering::Acquire) {
+                let _lock = lock(&shared.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl Drop for SpinningPool {
+    fn drop(&mut self) {
+        use std::sync::atomic::Ordering;
+
+        for (shared, handle) in self.threads.drain(..) {
+            *lock(&shared.0) = None;
+            shared.1.store(true, Ordering::Release);
+            handle.thread().unpark();
+            handle.join().unwrap();
+        }
+    }
+}
+
+/// Compares the queue with the spinning scheduler, the latency is the time
+/// from `execute` to the start of the task when the threads are idle, and the
+/// time the caller spends in `execute` when they are all busy
+///
+/// `cargo test --release -- --ignored --nocapture scheduler_latency_benchmark`
+#[test]
+#[ignore]
+fn scheduler_latency_benchmark() {
+    use std::time::{Duration, Instant};
+
+    let threads = num_cpus::get().max(2);
+    let runs = 2000;
+    let panics = Panics::default();
+    let queue = WorkerPool::new(threads);
+    let spinning = SpinningPool::new(threads);
+    // Time until the task starts, with all the threads idle
+    let start_latency = |execute: &dyn Fn(Worker<'static>), wait: &dyn Fn()| {
+        let mut total = Duration::ZERO;
+        for _ in 0..runs {
+            let start = Instant::now();
+            let (task, handle) = Task::new(move || start.elapsed(), &panics);
+            execute(task.into());
+            total += handle.join().unwrap();
+            wait();
+        }
+        total / runs
+    };
+    // Time spent in `execute` when there are twice as many tasks as threads
+    let execute_latency = |execute: &dyn Fn(Worker<'static>), wait: &dyn Fn()| {
+        let mut total = Duration::ZERO;
+        for _ in 0..runs / 10 {
+            for _ in 0..threads * 2 {
+                let (task, _) = Task::new(|| thread::sleep(Duration::from_micros(200)), &panics);
+                let start = Instant::now();
+                execute(task.into());
+                total += start.elapsed();
+            }
+            wait();
+        }
+        total / (runs / 10 * threads as u32 * 2)
+    };
+    let queued = |worker| queue.execute(worker);
+    let spun = |worker| spinning.execute(worker);
+    let (queue_wait, spinning_wait) = (|| queue.wait_all(), || spinning.wait_all());
+    println!(
+        "start: queue {:?}, spinning {:?}",
+        start_latency(&queued, &queue_wait),
+        start_latency(&spun, &spinning_wait)
+    );
+    println!(
+        "execute with busy threads: queue {:?}, spinning {:?}",
+        execute_latency(&queued, &queue_wait),
+        execute_latency(&spun, &spinning_wait)
+    );
+}
+
+/// Compares how many small tasks per second the two schedulers get through
+///
+/// `cargo test --release -- --ignored --nocapture scheduler_throughput_benchmark`
+#[test]
+#[ignore]
+fn scheduler_throughput_benchmark() {
+    use std::hint::black_box;
+    use std::time::Instant;
+
+    let threads = num_cpus::get().max(2);
+    let tasks = 100_000;
+    let panics = Panics::default();
+    let task = || {
+        Task::new(
+            || black_box((0..1000u32).fold(0u32, |a, b| a.wrapping_mul(31) ^ b)),
+            &panics,
+        )
+        .0
+    };
+
+    let queue = WorkerPool::new(threads);
+    let start = Instant::now();
+    for _ in 0..tasks {
+        queue.execute(task());
+    }
+    queue.wait_all();
+    let queued = tasks as f64 / start.elapsed().as_secs_f64();
+
+    let spinning = SpinningPool::new(threads);
+    let start = Instant::now();
+    for _ in 0..tasks {
+        spinning.execute(task().into());
+    }
+    spinning.wait_all();
+    let spun = tasks as f64 / start.elapsed().as_secs_f64();
+
+    println!(
+        "{} threads: queue {:.0} tasks/s, spinning {:.0} tasks/s",
+        threads, queued, spun
+    );
+}
+
+/// Two diffusion workers taking the rows of a tiny image, loom checks that in
+/// every interleaving they get the image done, without losing a wake-up, and
+/// that the result is the same as with a single worker
+///
+/// `RUSTFLAGS="--cfg loom" cargo test -p dither --release loom_`
+#[test]
+#[cfg(loom)]
+fn loom_diffusion_workers() {
+    use super::cell::FixedPixel;
+    use super::color::Color;
+    use super::kernel::DiffusionKernel;
+    use super::pixel::{Image, Rgb};
+
+    let kernel = DiffusionKernel::FLOYD_STEINBERG;
+    let palette = Palette::new(vec![Color::new(0, 0, 0), Color::new(255, 255, 255)]);
+    // The loom threads need `'static` data
+    let palette: &'static Palette = Box::leak(Box::new(palette));
+    // Three rows of two pixels
+    let image: Vec<u8> = (0..6u8).flat_map(|i| [i * 40; 3]).collect();
+    loom::model(move || {
+        let mut expected = image.clone();
+        let mut cells = [FixedPixel::default(); 6];
+        let mut single = Image::<Rgb>::new(&mut expected, 2, 3);
+        let rows = SharedRows::new(&mut cells, single.rows_to_dither());
+        DiffusionWorker::<_, Rgb>::new(rows, palette, kernel, false).run();
+
+        let pixels = Box::leak(image.clone().into_boxed_slice());
+        let shared = Box::leak(Box::new(Image::<Rgb>::new(pixels, 2, 3)));
+        let cells = Box::leak(Box::new([FixedPixel::default(); 6]));
+        let rows = SharedRows::new(cells, shared.rows_to_dither());
+        let mut helper = DiffusionWorker::<_, Rgb>::new(rows.clone(), palette, kernel, false);
+        let thread = loom::thread::spawn(move || helper.run());
+        DiffusionWorker::<_, Rgb>::new(rows.clone(), palette, kernel, false).run();
+        thread.join().unwrap();
+        let actual: Vec<u8> = (0..6)
+            .flat_map(|i| unsafe { rows.pixel(i % 2, i / 2) }.to_vec())
+            .collect();
+        assert_eq!(actual, expected);
+    });
+}
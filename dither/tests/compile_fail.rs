@@ -0,0 +1,10 @@
+//! The borrows that `WorkerPool::scope` has to reject, each file in
+//! `tests/compile_fail` must fail to compile with the error next to it
+//!
+//! `TRYBUILD=overwrite cargo test -p dither --test compile_fail` updates the
+//! errors after a change of the compiler messages
+#[test]
+#[cfg_attr(miri, ignore)]
+fn dangling_borrows() {
+    trybuild::TestCases::new().compile_fail("tests/compile_fail/*.rs");
+}
@@ -0,0 +1,17 @@
+// Two diffusions over the same image would write on the same pixels, the
+// first one keeps it borrowed until the end of the scope
+use dither::{diffuse, ClampedPixel, DiffusionKernel, Image, Preset, Rgb, WorkerPool};
+
+fn main() {
+    let pool = WorkerPool::new(1);
+    let palette = Preset::PICO_8.palette();
+    let mut data = vec![0; 16 * 3];
+    let mut image = Image::<Rgb>::new(&mut data, 4, 4);
+    let mut cells = vec![ClampedPixel; 16];
+    let mut others = vec![ClampedPixel; 16];
+    pool.scope(|scope| {
+        let kernel = DiffusionKernel::FLOYD_STEINBERG;
+        diffuse(&mut image, &mut cells, &palette, kernel, false, scope);
+        diffuse(&mut image, &mut others, &palette, kernel, false, scope);
+    });
+}
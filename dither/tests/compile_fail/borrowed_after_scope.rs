@@ -0,0 +1,12 @@
+// The data stays borrowed until the scope returns, so it can't be touched
+// while the workers are using it
+use dither::WorkerPool;
+
+fn main() {
+    let pool = WorkerPool::new(1);
+    let mut data = vec![1, 2, 3];
+    pool.scope(|scope| {
+        scope.spawn(|| data.push(4));
+        data.clear();
+    });
+}
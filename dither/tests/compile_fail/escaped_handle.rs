@@ -0,0 +1,10 @@
+// The handle of a scoped task can't outlive the scope either, a panic it
+// reported after the scope returned would get lost
+use dither::WorkerPool;
+
+fn main() {
+    let pool = WorkerPool::new(1);
+    let mut escaped = None;
+    pool.scope(|scope| escaped = Some(scope.spawn(|| panic!("lost"))));
+    drop(escaped);
+}
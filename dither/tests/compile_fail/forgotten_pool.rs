@@ -0,0 +1,11 @@
+// Outside of a scope nothing waits for the workers, the pool can even be
+// forgotten, so they can't borrow anything
+use dither::WorkerPool;
+
+fn main() {
+    let pool = WorkerPool::new(1);
+    let mut data = vec![1, 2, 3];
+    pool.spawn(|| data.push(4));
+    std::mem::forget(pool);
+    drop(data);
+}
@@ -0,0 +1,11 @@
+// The scope can't leave the closure, otherwise it could execute workers after
+// `WorkerPool::scope` has returned
+use dither::WorkerPool;
+
+fn main() {
+    let pool = WorkerPool::new(1);
+    let mut leaked = None;
+    pool.scope(|scope| leaked = Some(scope));
+    let data = vec![1, 2, 3];
+    leaked.unwrap().spawn(|| data.len());
+}
@@ -0,0 +1,12 @@
+// A task can't borrow what the closure of the scope owns, as it's gone before
+// the scope waits for the task
+use dither::WorkerPool;
+
+fn main() {
+    let pool = WorkerPool::new(1);
+    pool.scope(|scope| {
+        let data = vec![1, 2, 3];
+        let data = &data;
+        scope.spawn(move || data.len());
+    });
+}
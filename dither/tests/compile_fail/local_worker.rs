@@ -0,0 +1,13 @@
+// Same for the workers, the image has to outlive the scope
+use dither::{diffuse, ClampedPixel, DiffusionKernel, Image, Preset, Rgb, WorkerPool};
+
+fn main() {
+    let pool = WorkerPool::new(1);
+    let palette = Preset::PICO_8.palette();
+    let mut cells = vec![ClampedPixel; 16];
+    pool.scope(|scope| {
+        let mut data = vec![0; 16 * 3];
+        let mut image = Image::<Rgb>::new(&mut data, 4, 4);
+        diffuse(&mut image, &mut cells, &palette, DiffusionKernel::FLOYD_STEINBERG, false, scope);
+    });
+}
@@ -22,7 +22,7 @@ impl Texture {
             width as i32,
             height as i32,
             0,
-            gl::RGBA as u32,
+            gl::RGBA,
             gl::UNSIGNED_BYTE,
             data.as_ptr() as *const _,
         );
@@ -70,7 +70,7 @@ impl Texture {
             self.width as i32,
             self.height as i32,
             0,
-            gl::RGBA as u32,
+            gl::RGBA,
             gl::UNSIGNED_BYTE,
             data.as_ptr() as *const _,
         );
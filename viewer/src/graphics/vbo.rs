@@ -55,7 +55,7 @@ impl VertexBufferObject {
         gl::BufferSubData(
             gl::ARRAY_BUFFER,
             (offset * size_of::<T>()) as isize,
-            (data.len() * size_of::<T>()) as isize,
+            std::mem::size_of_val(data) as isize,
             data.as_ptr() as *const _,
         );
     }
@@ -15,32 +15,32 @@ use graphics::VertexArrayObject;
 use graphics::VertexBufferObject;
 use graphics::VertexShader;
 
-#[allow(unused)]
-mod fractal;
 use fractal::*;
 
-#[macro_use]
-mod dithering;
-use dithering::dither;
-use dithering::Color;
-use dithering::Palette;
-use dithering::WorkerPool;
+use dither::Color;
+use dither::WorkerPanic;
+use dither::{dither, dither_temporal, Temporal};
+use dither::{Image, PixelFormat, Rgba};
+
+mod options;
+use options::Options;
 
-use std::collections::VecDeque;
-use std::convert::TryFrom;
 use std::mem::size_of;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
-use std::str::FromStr;
 use std::time::Instant;
 
-mod math {
-    pub use std::f32::consts::*;
-    pub const TWO_THIRDS_PI: f32 = FRAC_PI_3 * 2.0;
-    pub const FOUR_THIRDS_PI: f32 = TWO_THIRDS_PI * 2.0;
-    pub const TWICE_PI: f32 = PI * 2.0;
-}
-
 fn main() -> Result<(), String> {
+    let Options {
+        iterations,
+        method,
+        metric,
+        palette,
+        adaptive,
+        temporal,
+        alpha,
+        pool,
+    } = Options::from_args()?;
     // Create the event loop
     let el = EventLoop::new();
     // Create the window builder
@@ -62,9 +62,19 @@ fn main() -> Result<(), String> {
     // FRACTAL PROGRAM
 
     // Load the vertex shader form the file and compile it
-    let vs = unsafe { VertexShader::from_file(Path::new("shaders/fractal.vert"))? };
+    let vs = unsafe {
+        VertexShader::from_file(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/fractal.vert"
+        )))?
+    };
     // Load the fragment shader form the file and compile it
-    let fs = unsafe { FragmentShader::from_file(Path::new("shaders/fractal.frag"))? };
+    let fs = unsafe {
+        FragmentShader::from_file(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/fractal.frag"
+        )))?
+    };
     // Link the shaders to the program and compile it
     let fractal_program = unsafe { Program::new(&vs, None, &fs)? };
 
@@ -72,9 +82,19 @@ fn main() -> Result<(), String> {
     drop(fs);
 
     // Load the vertex shader form the file and compile it
-    let vs = unsafe { VertexShader::from_file(Path::new("shaders/texture.vert"))? };
+    let vs = unsafe {
+        VertexShader::from_file(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/texture.vert"
+        )))?
+    };
     // Load the fragment shader form the file and compile it
-    let fs = unsafe { FragmentShader::from_file(Path::new("shaders/texture.frag"))? };
+    let fs = unsafe {
+        FragmentShader::from_file(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/texture.frag"
+        )))?
+    };
     // Link the shaders to the program and compile it
     let texture_program = unsafe { Program::new(&vs, None, &fs)? };
 
@@ -83,38 +103,37 @@ fn main() -> Result<(), String> {
 
     unsafe { Program::bind(&fractal_program) };
 
-    // Number of iterations
-    const ITERATIONS: u32 = 4;
-    let iterations = std::env::args()
-        .nth(1)
-        .as_deref()
-        .map(u32::from_str)
-        .map(|v| v.unwrap_or(ITERATIONS))
-        .unwrap_or(ITERATIONS);
+    /*
+        NOTE:
+        As the threads need to sync with each other and with the pool, using a number of threads
+        that exceeds the number of cpus in your system will make the os schedule the threads thus
+        blocking the system as that thread must continue working in order for the others to finish.
+        The thread pool, also, has to run on some thread thus reducing the number of simultaneous
+        workers to the number of the cpus minus one (but at least one), which is what the options
+        reserve unless the number of threads is given.
+    */
+    let pool = pool
+        .build()
+        .map_err(|e| format!("Can't create the worker pool: {}", e))?;
 
     // The number of tetrahedrons generated is equal to four to the nth power, where n is the number of iterations
     let size = 4usize.pow(iterations);
-    // Create a double-ended queue for storing the tetrahedrons
-    let mut vec = VecDeque::with_capacity(size);
-    // Insert the first tetrahedron in the queue
-    vec.push_back(Tetrahedron::regular(Point::new(0.0, -0.7, 0.0), 1.4, 0.0));
-    // For each iteration:
-    for i in 0..iterations {
-        // For each tetrahedron:
-        for _ in 0..4usize.pow(i) {
-            // Remove it from the list
-            let tetra = vec.pop_front().unwrap();
-            // Split it into four parts
-            let [a, b, c, d] = tetra.sierpinski_split();
-            // Push them at the end of the queue
-            vec.push_back(a);
-            vec.push_back(b);
-            vec.push_back(c);
-            vec.push_back(d);
+    let base = Tetrahedron::regular(Point::new(0.0, -0.7, 0.0), 1.4, 0.0);
+    // The four parts of the first split are generated by the pool, as the tetrahedrons
+    // are ordered by the one they come from, they can just be put one after the other
+    let vec: Vec<Tetrahedron> = match iterations {
+        0 => vec![base],
+        _ => {
+            let parts: Vec<_> = IntoIterator::into_iter(base.sierpinski_split())
+                .map(|part| pool.spawn(move || part.sierpinski(iterations - 1)))
+                .collect();
+            let mut vec = Vec::with_capacity(size);
+            for part in parts {
+                vec.extend(part.join().map_err(|e| e.to_string())?);
+            }
+            vec
         }
-    }
-    // Trasform the deque into a vector
-    let vec: Vec<Tetrahedron> = vec.into_iter().collect();
+    };
 
     let fra_vao = unsafe { VertexArrayObject::new() };
     let tex_vao = unsafe { VertexArrayObject::new() };
@@ -155,22 +174,12 @@ fn main() -> Result<(), String> {
     let mut time = 0.0;
     let mut counter = 0;
 
-    /*
-        NOTE:
-        As the threads need to sync with each other and with the pool, using a number of threads
-        that exceeds the number of cpus in your system will make the os schedule the threads thus
-        blocking the system as that thread must continue working in order for the others to finish.
-        The thread pool, also, has to run on some thread thus reducing the number of simultaneous
-        workers to the number of the cpus minus one.
-    */
-    let mut pool = WorkerPool::new(num_cpus::get() - 1);
-    let palette = Palette::new([
-        rgb![#ffffff],
-        rgb![#ff0000],
-        rgb![#00ff00],
-        rgb![#0000ff],
-        rgb![#000000],
-    ]);
+    // The pixels that the alpha makes transparent are left out of the dithering
+    let mut palette = palette
+        .with_metric(metric)
+        .with_transparent(alpha.is_some());
+    let mut frames = 0;
+    let mut temporal = temporal.map(Temporal::new);
 
     let mut win_width = 500;
     let mut win_height = 500;
@@ -186,7 +195,7 @@ fn main() -> Result<(), String> {
 
         *control_flow = ControlFlow::Poll;
         match event {
-            #[allow(clippy::single_match)]
+            #[allow(clippy::single_match, clippy::collapsible_match)]
             Event::DeviceEvent { event, .. } => match event {
                 DeviceEvent::Button { button: 1, state } => {
                     pressing = state == ElementState::Pressed;
@@ -207,8 +216,8 @@ fn main() -> Result<(), String> {
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::Resized(size) => {
                     window.resize(size);
-                    win_width = size.width as u32;
-                    win_height = size.height as u32;
+                    win_width = size.width;
+                    win_height = size.height;
                     unsafe {
                         texture.resize(win_width / 2, win_height / 2);
                         depthbuffer.resize(texture.width(), texture.height());
@@ -232,7 +241,7 @@ fn main() -> Result<(), String> {
             _matrix = [cos, 0.0, -sin, 0.0, 1.0, 0.0, sin, 0.0, cos];
             unsafe {
                 // Update the uniform which stores the matrix
-                gl::UniformMatrix3fv(mat_loc as i32, 1, gl::FALSE, _matrix.as_ptr());
+                gl::UniformMatrix3fv(mat_loc, 1, gl::FALSE, _matrix.as_ptr());
             }
             // Reset the timer
             elapsed = Instant::now();
@@ -255,7 +264,7 @@ fn main() -> Result<(), String> {
             }
             unsafe {
                 // Update the color uniform
-                gl::Uniform3f(color_loc as i32, _color.x, _color.y, _color.z);
+                gl::Uniform3f(color_loc, _color.x, _color.y, _color.z);
             }
             update = true; // Notify the change
         }
@@ -275,22 +284,54 @@ fn main() -> Result<(), String> {
                 if !pressing {
                     // let start = Instant::now();
 
+                    let (width, height) = (texture.width() as usize, texture.height() as usize);
                     let mut pixels = texture.pixels();
-                    let mut colors: Vec<Color> = pixels
-                        .chunks_exact_mut(4)
-                        .map(|v| &mut v[..3])
-                        .map(<&mut [u8; 3]>::try_from)
-                        .map(Result::unwrap)
-                        .map(Color::from)
-                        .collect();
-                    dither(
-                        &mut colors,
-                        texture.width() as usize,
-                        texture.height() as usize,
-                        &palette,
-                        pool.scope(),
-                    );
-                    texture.update(&pixels);
+                    if let Some(ref alpha) = alpha {
+                        alpha.prepare(&mut pixels, width);
+                    }
+                    if let Some(ref adaptive) = adaptive {
+                        if frames % adaptive.every == 0 {
+                            // The transparent pixels would make the palette darker
+                            let opaque = |p: &&[u8]| alpha.is_none() || p[3] != 0;
+                            let pixels = pixels.chunks_exact(4);
+                            let mut colors: Vec<Color> =
+                                pixels.clone().filter(opaque).map(Rgba::read).collect();
+                            if colors.is_empty() {
+                                colors = pixels.map(Rgba::read).collect();
+                            }
+                            palette = adaptive
+                                .quantizer
+                                .generate(&colors, adaptive.colors, &adaptive.locked, &pool)
+                                .with_metric(metric)
+                                .with_transparent(alpha.is_some());
+                            if let Some(ref mut temporal) = temporal {
+                                temporal.reset();
+                            }
+                        }
+                        frames += 1;
+                    }
+                    let mut image = Image::<Rgba>::new(&mut pixels, width, height);
+                    // A panicking worker loses the frame but not the viewer, the pool
+                    // replaces the threads that died on the next scope
+                    let dithered = panic::catch_unwind(AssertUnwindSafe(|| match temporal {
+                        // The history is reset by itself when the texture gets resized
+                        Some(ref mut temporal) => {
+                            dither_temporal(&mut image, &palette, &method, temporal, &pool)
+                        }
+                        None => dither(&mut image, &palette, &method, &pool),
+                    }));
+                    match dithered {
+                        Ok(()) => {
+                            if let Some(ref alpha) = alpha {
+                                alpha.finish(&mut pixels);
+                            }
+                            texture.update(&pixels);
+                        }
+                        Err(payload) => match payload.downcast::<WorkerPanic>() {
+                            Ok(panic) => eprintln!("{}, skipping the frame", panic),
+                            Err(payload) => panic::resume_unwind(payload),
+                        },
+                    }
 
                     // time += start.elapsed().as_secs_f64();
                     // counter += 1;
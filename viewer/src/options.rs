@@ -0,0 +1,229 @@
+use dither::{
+    Alpha, Color, Coverage, DiffusionKernel, Method, Metric, Palette, Preset, Quantizer, ScanOrder,
+    ThresholdMatrix, Tiling, WorkerPoolBuilder,
+};
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Default number of iterations
+const ITERATIONS: u32 = 4;
+
+/// Default size of the adaptive palette
+const COLORS: usize = 16;
+
+/// Default number of frames between two adaptive palettes
+const EVERY: u32 = 30;
+
+/// How the palette gets generated from the rendered frames
+pub struct Adaptive {
+    pub quantizer: Quantizer,
+    /// Number of colors of the palette, counting the locked ones
+    pub colors: usize,
+    /// Number of frames after which the palette is generated again
+    pub every: u32,
+    /// The colors that are always in the palette
+    pub locked: Vec<Color>,
+}
+
+/// The options that can be passed to the program from the command line
+///
+/// The first argument that is not an option is the number of iterations
+pub struct Options {
+    /// Number of times the base tetrahedron is splitted
+    pub iterations: u32,
+    /// Dithering algorithm, either error diffusion with the given kernel (`--kernel <name>`)
+    /// or ordered dithering with a Bayer matrix (`--bayer <size>`) or a blue noise one
+    /// (`--blue-noise <size>`) of the given size, error diffusion goes in serpentine
+    /// order when `--serpentine` is passed, in linear light when `--gamma-correct` is passed
+    /// and without truncating the error when `--accurate` is passed, it's split in tiles
+    /// of the given height when `--tiles <rows>[:<seam>]` is passed
+    pub method: Method,
+    /// How the closest palette color is found (`--metric <name>`), when error
+    /// diffusion is used the error is carried in the space of the metric
+    pub metric: Metric,
+    /// Palette loaded from a file (`--palette <file>`), the format is given by
+    /// the extension: `gpl`, `txt` (Paint.NET), `act`, `pal` (JASC) or `hex`,
+    /// or one of the built-in ones (`--preset <name>`)
+    pub palette: Palette,
+    /// Palette generated from the frame with the given quantizer (`--adaptive <name>`)
+    /// of `--colors <count>` colors, every `--every <frames>` frames, the colors passed
+    /// with `--lock <rrggbb>` are always kept
+    pub adaptive: Option<Adaptive>,
+    /// Error diffusion keeps the palette color of the pixels that change less than
+    /// the given threshold (in 8-bit steps) from the previous frame (`--temporal <threshold>`)
+    pub temporal: Option<u32>,
+    /// The alpha channel is made binary with a threshold or a Bayer pattern
+    /// (`--alpha <threshold|dithered>`), the colors are taken as premultiplied by
+    /// the alpha unless `--straight-alpha` is passed
+    pub alpha: Option<Alpha>,
+    /// The threads of the pool, by default one for each CPU but the one that renders,
+    /// or `--threads <count>` of them, with `--no-smt` the hyper-threads of a core
+    /// don't count as different CPUs, `--pin` pins each thread to its own CPU (only on
+    /// Linux), `--priority <low|normal|high>` is a hint for the scheduler, and
+    /// `--stack-size <KiB>` and `--thread-name <prefix>` configure the threads
+    pub pool: WorkerPoolBuilder,
+}
+
+impl Options {
+    pub fn from_args() -> Result<Self, String> {
+        let mut options = Self {
+            iterations: ITERATIONS,
+            method: Method::default(),
+            metric: Metric::default(),
+            palette: Preset::default().palette(),
+            adaptive: None,
+            temporal: None,
+            alpha: None,
+            // The thread that executes the workers also needs a CPU
+            pool: WorkerPoolBuilder::new().reserve(1),
+        };
+        let mut colors = COLORS;
+        let mut every = EVERY;
+        let mut locked = Vec::new();
+        let mut order = ScanOrder::Raster;
+        let mut gamma_correct = false;
+        let mut accurate = false;
+        let mut tiles = None;
+        let mut premultiplied = true;
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--kernel" => {
+                    let kernel: DiffusionKernel = value(&mut args, "--kernel")?;
+                    options.method = Method::Diffusion {
+                        kernel,
+                        order,
+                        gamma_correct,
+                        accurate,
+                        tiles,
+                    };
+                }
+                "--serpentine" => order = ScanOrder::Serpentine,
+                "--gamma-correct" => gamma_correct = true,
+                "--accurate" => accurate = true,
+                "--tiles" => tiles = Some(value::<Tiling>(&mut args, "--tiles")?),
+                "--metric" => options.metric = value(&mut args, "--metric")?,
+                "--palette" => {
+                    let path: PathBuf = value(&mut args, "--palette")?;
+                    let palette = Palette::load(&path)
+                        .map_err(|e| format!("Can't load {}: {}", path.display(), e))?;
+                    options.palette = palette;
+                }
+                "--adaptive" => {
+                    options.adaptive = Some(Adaptive {
+                        quantizer: value(&mut args, "--adaptive")?,
+                        colors: COLORS,
+                        every: EVERY,
+                        locked: Vec::new(),
+                    });
+                }
+                "--colors" => colors = value(&mut args, "--colors")?,
+                "--every" => every = value(&mut args, "--every")?,
+                "--lock" => {
+                    let color: String = value(&mut args, "--lock")?;
+                    locked.push(Color::from_str(color.trim_start_matches('#'))?);
+                }
+                "--temporal" => options.temporal = Some(value(&mut args, "--temporal")?),
+                "--alpha" => {
+                    let coverage: Coverage = value(&mut args, "--alpha")?;
+                    options.alpha = Some(Alpha {
+                        coverage,
+                        premultiplied,
+                    });
+                }
+                "--straight-alpha" => premultiplied = false,
+                "--threads" => {
+                    let threads: usize = value(&mut args, "--threads")?;
+                    if threads == 0 {
+                        return Err("The pool needs at least a thread".into());
+                    }
+                    options.pool = options.pool.threads(threads);
+                }
+                "--no-smt" => options.pool = options.pool.smt(false),
+                "--pin" => options.pool = options.pool.pin(true),
+                "--priority" => {
+                    options.pool = options.pool.priority(value(&mut args, "--priority")?)
+                }
+                "--stack-size" => {
+                    let kib: usize = value(&mut args, "--stack-size")?;
+                    options.pool = options.pool.stack_size(kib * 1024);
+                }
+                "--thread-name" => {
+                    options.pool = options
+                        .pool
+                        .name(value::<String>(&mut args, "--thread-name")?)
+                }
+                "--preset" => {
+                    let preset: Preset = value(&mut args, "--preset")?;
+                    options.palette = preset.palette();
+                }
+                "--bayer" => {
+                    let size: usize = value(&mut args, "--bayer")?;
+                    if size == 0 {
+                        return Err("The Bayer matrix size must be at least 1".into());
+                    }
+                    options.method = Method::Ordered(ThresholdMatrix::bayer(size));
+                }
+                "--blue-noise" => {
+                    let size: usize = value(&mut args, "--blue-noise")?;
+                    if size < 2 {
+                        return Err(format!(
+                            "The blue noise size must be at least 2, not {}",
+                            size
+                        ));
+                    }
+                    let cache = env::temp_dir().join(env!("CARGO_PKG_NAME"));
+                    let matrix = ThresholdMatrix::blue_noise_cached(size, &cache)
+                        .map_err(|e| e.to_string())?;
+                    options.method = Method::Ordered(matrix);
+                }
+                _ => options.iterations = u32::from_str(&arg).unwrap_or(ITERATIONS),
+            }
+        }
+        if let Method::Diffusion {
+            order: ref mut o,
+            gamma_correct: ref mut g,
+            accurate: ref mut a,
+            tiles: ref mut t,
+            ..
+        } = options.method
+        {
+            *o = order;
+            *g = gamma_correct;
+            *a = accurate;
+            *t = tiles;
+        }
+        if let Some(ref mut alpha) = options.alpha {
+            alpha.premultiplied = premultiplied;
+        }
+        if let Some(ref mut adaptive) = options.adaptive {
+            if colors == 0 || every == 0 {
+                return Err("The adaptive palette needs at least a color and a frame".into());
+            }
+            if locked.len() > colors {
+                return Err(format!(
+                    "Can't lock {} colors in a palette of {}",
+                    locked.len(),
+                    colors
+                ));
+            }
+            adaptive.colors = colors;
+            adaptive.every = every;
+            adaptive.locked = locked;
+        }
+        Ok(options)
+    }
+}
+
+/// Parses the argument that follows an option
+fn value<T>(args: &mut impl Iterator<Item = String>, option: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: ToString,
+{
+    args.next()
+        .ok_or_else(|| format!("Missing value for `{}`", option))?
+        .parse()
+        .map_err(|e: T::Err| e.to_string())
+}